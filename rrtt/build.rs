@@ -1,24 +1,13 @@
 ﻿//! See [cargo reference](https://doc.rust-lang.org/cargo/reference/build-scripts.html#cargorustc-cfgkeyvalue) for `rustc-cfg`.
 
-fn main() {
-    use std::env;
+use std::env;
 
+fn main() {
     const PRIORITY_MAX: &str = "PRIORITY_MAX";
     const PRIORITY_MAX_DEFAULT: usize = 8;
-    let val = match env::var(PRIORITY_MAX) {
-        Ok(s) => match s.parse::<usize>() {
-            Ok(val) => val,
-            Err(_) => panic!("failed to parse env {PRIORITY_MAX}={s:?} for not a number."),
-        },
-        Err(env::VarError::NotPresent) => {
-            println!("cargo:rustc-env={PRIORITY_MAX}={PRIORITY_MAX_DEFAULT}");
-            PRIORITY_MAX_DEFAULT
-        }
-        Err(env::VarError::NotUnicode(_)) => {
-            panic!("failed to parse env {PRIORITY_MAX} for not unicode.");
-        }
-    };
-    println!("cargo:rerun-if-env-changed={PRIORITY_MAX}");
+    let val = env_usize(PRIORITY_MAX, PRIORITY_MAX_DEFAULT);
+    println!("cargo:rustc-check-cfg=cfg(large_priority)");
+    println!("cargo:rustc-check-cfg=cfg(small_priority)");
     println!(
         "cargo:rustc-cfg={}",
         if val > 32 {
@@ -27,4 +16,68 @@ fn main() {
             "small_priority"
         }
     );
+
+    const TICK_PER_SECOND: &str = "TICK_PER_SECOND";
+    const TICK_PER_SECOND_DEFAULT: usize = 1000;
+    env_usize(TICK_PER_SECOND, TICK_PER_SECOND_DEFAULT);
+
+    // RISC-V core local interruptor and the frequency of its `mtime`
+    const CLINT_BASE: &str = "CLINT_BASE";
+    const CLINT_BASE_DEFAULT: usize = 0x0200_0000;
+    env_usize(CLINT_BASE, CLINT_BASE_DEFAULT);
+
+    const TIMEBASE_FREQUENCY: &str = "TIMEBASE_FREQUENCY";
+    const TIMEBASE_FREQUENCY_DEFAULT: usize = 10_000_000;
+    let timebase_frequency = env_usize(TIMEBASE_FREQUENCY, TIMEBASE_FREQUENCY_DEFAULT);
+
+    // core clock, counted by `mcycle` on RISC-V and by SysTick and the DWT on Cortex-M
+    const CPU_FREQUENCY: &str = "CPU_FREQUENCY";
+    let core_clock = env::var("CARGO_CFG_TARGET_ARCH")
+        .is_ok_and(|arch| arch == "arm" || arch.starts_with("riscv"));
+    if core_clock && env::var_os(CPU_FREQUENCY).is_none() {
+        println!(
+            "cargo:warning=env {CPU_FREQUENCY} not set, the core clock defaults to {TIMEBASE_FREQUENCY}={timebase_frequency}."
+        );
+    }
+    if env_usize(CPU_FREQUENCY, timebase_frequency) == 0 {
+        panic!("env {CPU_FREQUENCY} must not be 0.");
+    }
+
+    // interrupts with a handler table entry, all GICv2 interrupt IDs on AArch64
+    const IRQ_MAX: &str = "IRQ_MAX";
+    const IRQ_MAX_DEFAULT: usize = 32;
+    const IRQ_MAX_GIC: usize = 1020;
+    let gic = env::var("CARGO_CFG_TARGET_ARCH").is_ok_and(|arch| arch == "aarch64")
+        && env::var("CARGO_CFG_TARGET_OS").is_ok_and(|os| os == "none");
+    env_usize(IRQ_MAX, if gic { IRQ_MAX_GIC } else { IRQ_MAX_DEFAULT });
+
+    // AArch64 GICv2 distributor and CPU interface
+    const GICD_BASE: &str = "GICD_BASE";
+    const GICD_BASE_DEFAULT: usize = 0x0800_0000;
+    env_usize(GICD_BASE, GICD_BASE_DEFAULT);
+
+    const GICC_BASE: &str = "GICC_BASE";
+    const GICC_BASE_DEFAULT: usize = 0x0801_0000;
+    env_usize(GICC_BASE, GICC_BASE_DEFAULT);
+}
+
+/// Reads a numeric config from env `key`, decimal or `0x` hexadecimal,
+/// and passes it to rustc in decimal, or `default` if not present.
+fn env_usize(key: &str, default: usize) -> usize {
+    let val = match env::var(key) {
+        Ok(s) => match s
+            .strip_prefix("0x")
+            .map_or_else(|| s.parse::<usize>(), |hex| usize::from_str_radix(hex, 16))
+        {
+            Ok(val) => val,
+            Err(_) => panic!("failed to parse env {key}={s:?} for not a number."),
+        },
+        Err(env::VarError::NotPresent) => default,
+        Err(env::VarError::NotUnicode(_)) => {
+            panic!("failed to parse env {key} for not unicode.");
+        }
+    };
+    println!("cargo:rustc-env={key}={val}");
+    println!("cargo:rerun-if-env-changed={key}");
+    val
 }
@@ -0,0 +1,170 @@
+//! See [the c code](https://github.com/RT-Thread/rt-thread/blob/v5.1.0/include/rtatomic.h).
+//!
+//! Targets with compare-and-swap use the native atomics,
+//! the others such as Cortex-M0 do each operation with interrupts disabled.
+
+#[cfg(not(target_has_atomic = "ptr"))]
+use crate::cpu::InterruptFreeGuard;
+#[cfg(not(target_has_atomic = "ptr"))]
+use core::cell::UnsafeCell;
+#[cfg(target_has_atomic = "ptr")]
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+/// An integer shared between threads and interrupt handlers.
+///
+/// Every operation is sequentially consistent,
+/// and the read-modify-write ones return the previous value.
+#[repr(transparent)]
+pub struct Atomic {
+    #[cfg(target_has_atomic = "ptr")]
+    value: AtomicUsize,
+    #[cfg(not(target_has_atomic = "ptr"))]
+    value: UnsafeCell<usize>,
+}
+
+#[cfg(not(target_has_atomic = "ptr"))]
+unsafe impl Sync for Atomic {}
+
+#[cfg(target_has_atomic = "ptr")]
+impl Atomic {
+    #[inline]
+    pub const fn new(value: usize) -> Self {
+        Self {
+            value: AtomicUsize::new(value),
+        }
+    }
+
+    #[inline]
+    pub fn load(&self) -> usize {
+        self.value.load(SeqCst)
+    }
+
+    #[inline]
+    pub fn store(&self, value: usize) {
+        self.value.store(value, SeqCst)
+    }
+
+    #[inline]
+    pub fn add(&self, value: usize) -> usize {
+        self.value.fetch_add(value, SeqCst)
+    }
+
+    #[inline]
+    pub fn sub(&self, value: usize) -> usize {
+        self.value.fetch_sub(value, SeqCst)
+    }
+
+    #[inline]
+    pub fn and(&self, value: usize) -> usize {
+        self.value.fetch_and(value, SeqCst)
+    }
+
+    #[inline]
+    pub fn or(&self, value: usize) -> usize {
+        self.value.fetch_or(value, SeqCst)
+    }
+
+    #[inline]
+    pub fn xor(&self, value: usize) -> usize {
+        self.value.fetch_xor(value, SeqCst)
+    }
+
+    #[inline]
+    pub fn exchange(&self, value: usize) -> usize {
+        self.value.swap(value, SeqCst)
+    }
+
+    /// Stores `new` if the value is `current`, returning the previous value in `Ok` if it did.
+    #[inline]
+    pub fn compare_exchange(&self, current: usize, new: usize) -> Result<usize, usize> {
+        self.value.compare_exchange(current, new, SeqCst, SeqCst)
+    }
+}
+
+#[cfg(not(target_has_atomic = "ptr"))]
+impl Atomic {
+    #[inline]
+    pub const fn new(value: usize) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    #[inline]
+    pub fn load(&self) -> usize {
+        let _guard = InterruptFreeGuard::new();
+        unsafe { self.value.get().read_volatile() }
+    }
+
+    #[inline]
+    pub fn store(&self, value: usize) {
+        let _guard = InterruptFreeGuard::new();
+        unsafe { self.value.get().write_volatile(value) }
+    }
+
+    #[inline]
+    pub fn add(&self, value: usize) -> usize {
+        self.update(|old| old.wrapping_add(value))
+    }
+
+    #[inline]
+    pub fn sub(&self, value: usize) -> usize {
+        self.update(|old| old.wrapping_sub(value))
+    }
+
+    #[inline]
+    pub fn and(&self, value: usize) -> usize {
+        self.update(|old| old & value)
+    }
+
+    #[inline]
+    pub fn or(&self, value: usize) -> usize {
+        self.update(|old| old | value)
+    }
+
+    #[inline]
+    pub fn xor(&self, value: usize) -> usize {
+        self.update(|old| old ^ value)
+    }
+
+    #[inline]
+    pub fn exchange(&self, value: usize) -> usize {
+        self.update(|_| value)
+    }
+
+    /// Stores `new` if the value is `current`, returning the previous value in `Ok` if it did.
+    pub fn compare_exchange(&self, current: usize, new: usize) -> Result<usize, usize> {
+        let _guard = InterruptFreeGuard::new();
+        let old = unsafe { self.value.get().read_volatile() };
+        if old != current {
+            return Err(old);
+        }
+        unsafe { self.value.get().write_volatile(new) };
+        Ok(old)
+    }
+
+    /// Replaces the value with `f` of it and returns the previous one.
+    #[inline]
+    fn update(&self, f: impl FnOnce(usize) -> usize) -> usize {
+        let _guard = InterruptFreeGuard::new();
+        let old = unsafe { self.value.get().read_volatile() };
+        unsafe { self.value.get().write_volatile(f(old)) };
+        old
+    }
+}
+
+#[test]
+fn test_atomic() {
+    let atomic = Atomic::new(5);
+    assert_eq!(atomic.add(3), 5);
+    assert_eq!(atomic.sub(10), 8);
+    assert_eq!(atomic.load(), usize::MAX - 1);
+    atomic.store(0b1100);
+    assert_eq!(atomic.and(0b1010), 0b1100);
+    assert_eq!(atomic.or(0b0001), 0b1000);
+    assert_eq!(atomic.xor(0b1111), 0b1001);
+    assert_eq!(atomic.exchange(7), 0b0110);
+    assert_eq!(atomic.compare_exchange(6, 1), Err(7));
+    assert_eq!(atomic.compare_exchange(7, 1), Ok(7));
+    assert_eq!(atomic.load(), 1);
+}
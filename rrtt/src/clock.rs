@@ -0,0 +1,39 @@
+use crate::{cpu::InterruptFreeCell, scheduler, thread, timer, TICK_PER_SECOND};
+
+static TICK: InterruptFreeCell<usize> = InterruptFreeCell::new(0);
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/clock.c).
+#[inline]
+pub fn tick_get() -> usize {
+    TICK.with(|tick| *tick)
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/clock.c).
+pub fn tick_set(tick: usize) {
+    TICK.with(|t| *t = tick);
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/clock.c).
+pub fn tick_increase() {
+    TICK.with(|tick| *tick = tick.wrapping_add(1));
+
+    if let Some(thread) = unsafe { scheduler::current_thread().as_mut() } {
+        thread.remain_tick -= 1;
+        if thread.remain_tick == 0 {
+            thread.remain_tick = thread.init_tick;
+            thread::yield_now();
+        }
+    }
+
+    timer::check();
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/clock.c).
+pub fn tick_from_millisecond(ms: i32) -> usize {
+    if ms < 0 {
+        usize::MAX
+    } else {
+        let ms = ms as usize;
+        TICK_PER_SECOND * (ms / 1000) + (TICK_PER_SECOND * (ms % 1000)).div_ceil(1000)
+    }
+}
@@ -1,35 +1,113 @@
-﻿cfg_if::cfg_if! {
-    if #[cfg(target_arch = "arm")] {
-        #[inline]
-        pub fn interrupt_disable() -> usize {
-            let x: usize;
-            core::arch::asm!(
-            "   MRS   {}, PRIMASK
-                CPSID I
-            ",
-                out(reg) x
-            );
-            x
-        }
+﻿use crate::{
+    port::{Current, Port},
+    TICK_PER_SECOND,
+};
+use core::{
+    cell::{Cell, UnsafeCell},
+    ptr::NonNull,
+};
 
-        #[inline]
-        pub fn interrupt_enable(reg: usize) {
-            core::arch::asm!(
-            "
-                MSR   PRIMASK, {x}
-                CPSID I
-            ",
-                in(reg) reg
-            )
-        }
-    } else {
-        #[inline]
-        pub fn interrupt_disable() -> usize {
-            0
-        }
+/// See [`Port::STACK_MIN`].
+pub const STACK_MIN: usize = Current::STACK_MIN;
+
+/// See [`Port::interrupt_disable`].
+#[inline]
+pub fn interrupt_disable() -> usize {
+    Current::interrupt_disable()
+}
+
+/// See [`Port::interrupt_enable`].
+#[inline]
+pub fn interrupt_enable(level: usize) {
+    Current::interrupt_enable(level)
+}
+
+/// See [`Port::interrupt_mask`].
+#[inline]
+pub fn interrupt_mask(irq: usize) {
+    Current::interrupt_mask(irq)
+}
+
+/// See [`Port::interrupt_unmask`].
+#[inline]
+pub fn interrupt_unmask(irq: usize) {
+    Current::interrupt_unmask(irq)
+}
+
+/// See [`Port::stack_init`].
+///
+/// # Safety
+///
+/// See [`Port::stack_init`].
+#[inline]
+pub unsafe fn stack_init(
+    entry: extern "C" fn(usize),
+    parameter: usize,
+    stack_addr: *mut u8,
+    exit: extern "C" fn() -> !,
+) -> *mut u8 {
+    Current::stack_init(entry, parameter, stack_addr, exit)
+}
+
+/// See [`Port::context_switch`].
+///
+/// # Safety
+///
+/// See [`Port::context_switch`].
+#[inline]
+pub unsafe fn context_switch(from: *mut NonNull<usize>, to: *mut NonNull<usize>) {
+    Current::context_switch(from, to)
+}
+
+/// See [`Port::context_switch_to`].
+///
+/// # Safety
+///
+/// See [`Port::context_switch_to`].
+#[inline]
+pub unsafe fn context_switch_to(to: *mut NonNull<usize>) {
+    Current::context_switch_to(to)
+}
 
-        #[inline]
-        pub fn interrupt_enable(_reg: usize) {}
+/// See [`Port::context_switch_interrupt`].
+///
+/// # Safety
+///
+/// See [`Port::context_switch_interrupt`].
+#[inline]
+pub unsafe fn context_switch_interrupt(from: *mut NonNull<usize>, to: *mut NonNull<usize>) {
+    Current::context_switch_interrupt(from, to)
+}
+
+/// Starts the tick source at [`TICK_PER_SECOND`].
+#[inline]
+pub fn tick_init() {
+    Current::tick_init(TICK_PER_SECOND)
+}
+
+/// See [`Port::cycle_get`].
+#[inline]
+pub fn cycle_get() -> u64 {
+    Current::cycle_get()
+}
+
+/// See [`Port::cycle_frequency`].
+#[inline]
+pub fn cycle_frequency() -> u64 {
+    Current::cycle_frequency()
+}
+
+/// Converts cycles of [`cycle_get`] to nanoseconds,
+/// saturating at `u64::MAX`, or 0 if the port reports no cycle frequency.
+#[inline]
+pub fn cycle_to_ns(cycles: u64) -> u64 {
+    cycles_to_ns(cycles, cycle_frequency())
+}
+
+fn cycles_to_ns(cycles: u64, frequency: u64) -> u64 {
+    match frequency {
+        0 => 0,
+        _ => (cycles as u128 * 1_000_000_000 / frequency as u128).min(u64::MAX as u128) as u64,
     }
 }
 
@@ -48,3 +126,131 @@ impl Drop for InterruptFreeGuard {
         interrupt_enable(self.0)
     }
 }
+
+/// A global of the kernel, only reached with interrupts disabled.
+///
+/// The value is lent to a closure, so the compiler rejects any reference to it outliving the critical section.
+/// A nested access to the same cell from the closure is only caught at run time,
+/// where [`InterruptFreeCell::with`] panics instead of aliasing.
+pub struct InterruptFreeCell<T> {
+    value: UnsafeCell<T>,
+    borrowed: Cell<bool>,
+}
+
+// Safety: the kernel runs on a single core, and the value is only reached with interrupts disabled.
+unsafe impl<T> Sync for InterruptFreeCell<T> {}
+
+impl<T> InterruptFreeCell<T> {
+    #[inline]
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+            borrowed: Cell::new(false),
+        }
+    }
+
+    /// Calls `f` with the value, with interrupts disabled.
+    ///
+    /// Panics if called from `f` on the same cell.
+    #[inline]
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        self.try_with(f).expect("kernel global already borrowed")
+    }
+
+    /// Calls `f` with the value like [`InterruptFreeCell::with`],
+    /// or returns `None` if the value is already lent,
+    /// for code such as the fault report that may run inside [`InterruptFreeCell::with`].
+    #[inline]
+    pub fn try_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        struct Borrow<'a>(&'a Cell<bool>);
+
+        impl Drop for Borrow<'_> {
+            #[inline]
+            fn drop(&mut self) {
+                self.0.set(false);
+            }
+        }
+
+        let _guard = InterruptFreeGuard::new();
+        if self.borrowed.replace(true) {
+            return None;
+        }
+        let _borrow = Borrow(&self.borrowed);
+        Some(f(unsafe { &mut *self.value.get() }))
+    }
+
+    /// Pointer to the value, for statics the kernel links to for good,
+    /// such as list heads in initializers or the control block of the idle thread.
+    ///
+    /// Dereferencing it bypasses the borrow check of [`InterruptFreeCell::with`],
+    /// so the value must not be lent at the same time,
+    /// and a reference made from it must not be used from an interrupt that may preempt the holder.
+    #[inline]
+    pub(crate) const fn as_ptr(&self) -> *mut T {
+        self.value.get()
+    }
+}
+
+/// Critical sections of the ecosystem crates, on the interrupt state of the port.
+#[cfg(feature = "critical-section")]
+struct CriticalSection;
+
+#[cfg(feature = "critical-section")]
+critical_section::set_impl!(CriticalSection);
+
+#[cfg(feature = "critical-section")]
+unsafe impl critical_section::Impl for CriticalSection {
+    #[inline]
+    unsafe fn acquire() -> critical_section::RawRestoreState {
+        interrupt_disable()
+    }
+
+    #[inline]
+    unsafe fn release(level: critical_section::RawRestoreState) {
+        interrupt_enable(level)
+    }
+}
+
+#[test]
+fn test_try_with() {
+    let cell = InterruptFreeCell::new(1);
+    assert_eq!(
+        cell.with(|outer| cell.try_with(|_| ()).map(|_| *outer)),
+        None
+    );
+    assert_eq!(cell.try_with(|value| *value), Some(1));
+}
+
+#[test]
+#[should_panic(expected = "kernel global already borrowed")]
+fn test_nested_borrow() {
+    let cell = InterruptFreeCell::new(0);
+    cell.with(|_| cell.with(|value| *value += 1));
+}
+
+#[test]
+#[cfg(feature = "critical-section")]
+fn test_critical_section() {
+    let _lock = crate::test_lock();
+    critical_section::with(|_| {
+        assert_eq!(interrupt_disable(), 1);
+        critical_section::with(|_| assert_eq!(interrupt_disable(), 1));
+        assert_eq!(interrupt_disable(), 1);
+    });
+    let level = interrupt_disable();
+    interrupt_enable(level);
+    assert_eq!(level, 0);
+}
+
+#[test]
+fn test_cycle() {
+    let start = cycle_get();
+    std::thread::sleep(core::time::Duration::from_millis(2));
+    assert!(cycle_to_ns(cycle_get() - start) >= 2_000_000);
+
+    assert_eq!(cycles_to_ns(48_000_000, 48_000_000), 1_000_000_000);
+    assert_eq!(cycles_to_ns(3, 48_000_000), 62);
+    assert_eq!(cycles_to_ns(u64::MAX / 2, 4_000_000_000), u64::MAX / 8);
+    assert_eq!(cycles_to_ns(u64::MAX, 48_000_000), u64::MAX);
+    assert_eq!(cycles_to_ns(1_000, 0), 0);
+}
@@ -0,0 +1,71 @@
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/include/rtdef.h).
+///
+/// `RT_EOK` is represented by `Ok` of a [`Result`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Error {
+    /// A generic error happens
+    Generic = 1,
+    /// Timed out
+    Timeout = 2,
+    /// The resource is full
+    Full = 3,
+    /// The resource is empty
+    Empty = 4,
+    /// No memory
+    NoMem = 5,
+    /// No system
+    NoSys = 6,
+    /// Busy
+    Busy = 7,
+    /// IO error
+    Io = 8,
+    /// Interrupted system call
+    Intr = 9,
+    /// Invalid argument
+    Inval = 10,
+}
+
+impl Error {
+    /// Converts a kernel result into the `rt_err_t` value the c code returns.
+    #[inline]
+    pub const fn to_code(result: Result<(), Self>) -> isize {
+        match result {
+            Ok(()) => 0,
+            Err(e) => -(e as isize),
+        }
+    }
+
+    /// Converts an `rt_err_t` value into a kernel result.
+    pub const fn from_code(code: isize) -> Result<(), Self> {
+        match -code {
+            0 => Ok(()),
+            2 => Err(Self::Timeout),
+            3 => Err(Self::Full),
+            4 => Err(Self::Empty),
+            5 => Err(Self::NoMem),
+            6 => Err(Self::NoSys),
+            7 => Err(Self::Busy),
+            8 => Err(Self::Io),
+            9 => Err(Self::Intr),
+            10 => Err(Self::Inval),
+            _ => Err(Self::Generic),
+        }
+    }
+}
+
+#[test]
+fn test_code() {
+    use Error::*;
+
+    assert_eq!(Error::to_code(Ok(())), 0);
+    assert_eq!(Error::from_code(0), Ok(()));
+    for e in [
+        Generic, Timeout, Full, Empty, NoMem, NoSys, Busy, Io, Intr, Inval,
+    ] {
+        let code = Error::to_code(Err(e));
+        assert!(code < 0);
+        assert_eq!(Error::from_code(code), Err(e));
+    }
+    assert_eq!(Error::from_code(-100), Err(Generic));
+}
@@ -0,0 +1,170 @@
+//! See [the c code](https://github.com/RT-Thread/rt-thread/blob/v5.1.0/libcpu/arm/cortex-m4/cpuport.c).
+//!
+//! The ports report the exceptions the kernel does not handle with [`handle`],
+//! which prints the registers, the current thread and a backtrace through the console,
+//! gives the report to the hook set by [`set_hook`] and panics.
+//!
+//! The backtrace follows the frame pointers on the stack of the current thread,
+//! so it only goes further than the faulting function if the code keeps frame pointers,
+//! as with `-C force-frame-pointers=yes`.
+
+use crate::{cpu::InterruptFreeCell, kservice::kprintf, scheduler};
+use core::{fmt, mem::size_of};
+
+/// Frames printed by the backtrace at most
+const BACKTRACE_MAX: usize = 16;
+
+/// Word offsets from a frame pointer of the saved frame pointer of the caller and the return address.
+#[cfg(any(target_arch = "arm", target_arch = "aarch64", target_arch = "x86_64"))]
+const FRAME_RECORD: Option<(isize, isize)> = Some((0, 1));
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+const FRAME_RECORD: Option<(isize, isize)> = Some((-2, -1));
+#[cfg(not(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "x86_64",
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+)))]
+const FRAME_RECORD: Option<(isize, isize)> = None;
+
+static FAULT_HOOK: InterruptFreeCell<Option<fn(&Fault)>> = InterruptFreeCell::new(None);
+
+/// The state of the CPU at an exception the kernel does not recover from.
+///
+/// Formats as the report printed by [`handle`].
+pub struct Fault<'a> {
+    /// What went wrong
+    pub kind: &'a str,
+    /// Registers saved or read by the port, in the order they are printed
+    pub registers: &'a [(&'static str, usize)],
+    /// Frame pointer of the faulting code, where the backtrace starts
+    pub frame_pointer: usize,
+}
+
+/// Sets a hook called with the report of a fault before the panic,
+/// where a product may persist it before the system resets.
+pub fn set_hook(hook: Option<fn(&Fault)>) {
+    FAULT_HOOK.with(|fault_hook| *fault_hook = hook);
+}
+
+/// Reports `fault` through the console and the hook, then panics.
+pub(crate) fn handle(fault: &Fault) -> ! {
+    kprintf(format_args!("{fault}"));
+    if let Some(hook) = FAULT_HOOK.try_with(|hook| *hook).flatten() {
+        hook(fault);
+    }
+    panic!("{}", fault.kind)
+}
+
+impl fmt::Display for Fault<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const WIDTH: usize = 2 + 2 * size_of::<usize>();
+
+        writeln!(f, "{}", self.kind)?;
+        for (i, (name, value)) in self.registers.iter().enumerate() {
+            write!(f, "{name:>8}: {value:#0WIDTH$x}")?;
+            if i % 4 == 3 || i == self.registers.len() - 1 {
+                writeln!(f)?;
+            }
+        }
+
+        // the fault may be raised while the scheduler holds the current thread
+        let Some(current) = scheduler::try_current_thread() else {
+            return writeln!(f, "thread: unknown");
+        };
+        let Some(thread) = (unsafe { current.as_ref() }) else {
+            return writeln!(f, "thread: none");
+        };
+        let bottom = thread.stack_address.as_ptr() as usize;
+        let top = bottom + thread.stack_size;
+        writeln!(
+            f,
+            "thread: {}, stack: {bottom:#x}..{top:#x}",
+            thread.header.name(),
+        )?;
+
+        let Some((caller, ret)) = FRAME_RECORD else {
+            return Ok(());
+        };
+        writeln!(f, "backtrace:")?;
+        let mut fp = self.frame_pointer;
+        for i in 0..BACKTRACE_MAX {
+            // the record must lie on the stack
+            let first = fp.wrapping_add_signed(caller.min(ret) * size_of::<usize>() as isize);
+            let last = fp.wrapping_add_signed(caller.max(ret) * size_of::<usize>() as isize);
+            if !fp.is_multiple_of(size_of::<usize>()) || first < bottom || last >= top {
+                break;
+            }
+            let record = fp as *const usize;
+            let (next, ra) = unsafe { (*record.offset(caller), *record.offset(ret)) };
+            writeln!(f, "  #{i} {ra:#x}")?;
+            // callers have their frames higher on the stack
+            if next <= fp {
+                break;
+            }
+            fp = next;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_report() {
+    use crate::thread::Thread;
+    use core::mem::MaybeUninit;
+    use std::{boxed::Box, format};
+
+    let _lock = crate::test_lock();
+    crate::object::reset();
+
+    extern "C" fn entry(_: usize) {}
+    let stack: &'static mut [usize; 64] = Box::leak(Box::new([0; 64]));
+    let base = stack.as_mut_ptr();
+    let thread = Box::leak(Box::new(MaybeUninit::<Thread>::uninit()));
+    Thread::init(thread, "faulty", entry, 0, stack, 1, 10);
+    scheduler::CURRENT_THREAD.with(|current| *current = thread.as_mut_ptr());
+
+    // two frame records on the stack
+    let (first, second) = (base.wrapping_add(8), base.wrapping_add(16));
+    let (caller, ret) = FRAME_RECORD.unwrap();
+    unsafe {
+        *first.offset(caller) = second as usize;
+        *first.offset(ret) = 0x1000;
+        *second.offset(caller) = 0;
+        *second.offset(ret) = 0x2000;
+    }
+    let report = format!(
+        "{}",
+        Fault {
+            kind: "test fault",
+            registers: &[("pc", 0x1234), ("sp", first as usize)],
+            frame_pointer: first as usize,
+        }
+    );
+    scheduler::CURRENT_THREAD.with(|current| *current = core::ptr::null_mut());
+    crate::object::reset();
+
+    assert!(report.starts_with("test fault\n"));
+    assert!(report.contains("pc: 0x"));
+    assert!(report.contains("thread: faulty"));
+    assert!(report.contains("#0 0x1000\n  #1 0x2000\n"));
+}
+
+#[test]
+fn test_report_in_scheduler() {
+    use std::format;
+
+    let _lock = crate::test_lock();
+    let report = scheduler::CURRENT_THREAD.with(|_| {
+        format!(
+            "{}",
+            Fault {
+                kind: "nested fault",
+                registers: &[("pc", 0)],
+                frame_pointer: 0,
+            }
+        )
+    });
+    assert!(report.ends_with("thread: unknown\n"));
+}
@@ -0,0 +1,101 @@
+use crate::{
+    cpu::{self, InterruptFreeCell, InterruptFreeGuard},
+    error::Error,
+    scheduler::THREAD_DEFUNCT,
+    thread::Thread,
+    PRIORITY_MAX,
+};
+use core::{
+    mem::{size_of, MaybeUninit},
+    ptr::NonNull,
+};
+
+/// 256 bytes for the idle thread on top of what the port takes
+const IDLE_THREAD_STACK_SIZE: usize = cpu::STACK_MIN + 256;
+const IDLE_HOOK_LIST_SIZE: usize = 4;
+
+type HookList = [Option<fn()>; IDLE_HOOK_LIST_SIZE];
+
+static IDLE_HOOK_LIST: InterruptFreeCell<HookList> =
+    InterruptFreeCell::new([None; IDLE_HOOK_LIST_SIZE]);
+
+static IDLE: InterruptFreeCell<MaybeUninit<Thread>> = InterruptFreeCell::new(MaybeUninit::uninit());
+static IDLE_THREAD_STACK: InterruptFreeCell<[usize; IDLE_THREAD_STACK_SIZE / size_of::<usize>()]> =
+    InterruptFreeCell::new([0; IDLE_THREAD_STACK_SIZE / size_of::<usize>()]);
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/idle.c).
+///
+/// `hook` is called by the idle thread, so it must not block.
+pub fn set_hook(hook: fn()) -> Result<(), Error> {
+    IDLE_HOOK_LIST.with(|list| {
+        let slot = list.iter_mut().find(|h| h.is_none()).ok_or(Error::Full)?;
+        *slot = Some(hook);
+        Ok(())
+    })
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/idle.c).
+pub fn del_hook(hook: fn()) -> Result<(), Error> {
+    IDLE_HOOK_LIST.with(|list| {
+        let slot = list
+            .iter_mut()
+            .find(|h| h.is_some_and(|h| core::ptr::fn_addr_eq(h, hook)))
+            .ok_or(Error::NoSys)?;
+        *slot = None;
+        Ok(())
+    })
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/idle.c).
+pub fn excute() {
+    loop {
+        let thread = {
+            let _guard = InterruptFreeGuard::new();
+            let Some(next) = THREAD_DEFUNCT.with(|defunct| {
+                let defunct = unsafe { defunct.assume_init_ref() };
+                (!defunct.is_empty()).then_some(defunct.next)
+            }) else {
+                return;
+            };
+            let thread = unsafe { &mut *container_of!(next.as_ptr(), Thread, list).cast_mut() };
+            thread.list.remove();
+            thread
+        };
+
+        #[cfg(feature = "mem-heap")]
+        unsafe {
+            alloc::alloc::dealloc(
+                thread.stack_address.as_ptr().cast(),
+                crate::thread::stack_layout(thread.stack_size),
+            );
+            crate::object::Object::delete(NonNull::from(thread).cast());
+        }
+    }
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/idle.c).
+extern "C" fn idle_entry(_parameter: usize) {
+    loop {
+        excute();
+
+        for hook in IDLE_HOOK_LIST.with(|list| *list).into_iter().flatten() {
+            hook();
+        }
+    }
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/idle.c).
+pub fn init() {
+    // linked into the scheduler for good, so they are handed over without a borrow
+    let (idle, stack) = unsafe { (&mut *IDLE.as_ptr(), &mut *IDLE_THREAD_STACK.as_ptr()) };
+    Thread::init(
+        idle,
+        "tidle",
+        idle_entry,
+        0,
+        stack,
+        (PRIORITY_MAX - 1) as u8,
+        32,
+    );
+    unsafe { idle.assume_init_mut() }.startup();
+}
@@ -0,0 +1,193 @@
+use crate::{
+    cpu::{self, InterruptFreeCell},
+    error::Error,
+    IRQ_MAX,
+};
+
+/// An interrupt handler, called with the interrupt number.
+pub type Handler = &'static (dyn Fn(usize) + Sync);
+
+/// See [the c code](https://github.com/RT-Thread/rt-thread/blob/v5.1.0/include/rthw.h).
+#[derive(Clone, Copy)]
+struct IrqDesc {
+    handler: Option<Handler>,
+    name: &'static str,
+    counter: usize,
+}
+
+static INTERRUPT_NEST: InterruptFreeCell<u8> = InterruptFreeCell::new(0);
+static INTERRUPT_ENTER_HOOK: InterruptFreeCell<Option<fn()>> = InterruptFreeCell::new(None);
+static INTERRUPT_LEAVE_HOOK: InterruptFreeCell<Option<fn()>> = InterruptFreeCell::new(None);
+static IRQ_DESC: InterruptFreeCell<[IrqDesc; IRQ_MAX]> = InterruptFreeCell::new(
+    [IrqDesc {
+        handler: None,
+        name: "",
+        counter: 0,
+    }; IRQ_MAX],
+);
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/irq.c).
+pub fn enter_sethook(hook: Option<fn()>) {
+    INTERRUPT_ENTER_HOOK.with(|enter_hook| *enter_hook = hook);
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/irq.c).
+pub fn leave_sethook(hook: Option<fn()>) {
+    INTERRUPT_LEAVE_HOOK.with(|leave_hook| *leave_hook = hook);
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/irq.c).
+///
+/// Called by an interrupt handler before calling into the kernel.
+pub fn enter() {
+    let _guard = cpu::InterruptFreeGuard::new();
+    INTERRUPT_NEST.with(|nest| *nest += 1);
+    if let Some(hook) = INTERRUPT_ENTER_HOOK.with(|hook| *hook) {
+        hook();
+    }
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/irq.c).
+///
+/// A switch scheduled in the handler is made once the outermost handler returns.
+pub fn leave() {
+    let _guard = cpu::InterruptFreeGuard::new();
+    if let Some(hook) = INTERRUPT_LEAVE_HOOK.with(|hook| *hook) {
+        hook();
+    }
+    INTERRUPT_NEST.with(|nest| *nest -= 1);
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/irq.c).
+#[inline]
+pub fn get_nest() -> u8 {
+    INTERRUPT_NEST.with(|nest| *nest)
+}
+
+/// Whether the caller runs in an interrupt handler.
+#[inline]
+pub fn in_interrupt() -> bool {
+    get_nest() != 0
+}
+
+/// Fails a blocking call from an interrupt handler with [`Error::Inval`],
+/// which is a bug caught by an assertion in debug builds.
+///
+/// See [the c code](https://github.com/RT-Thread/rt-thread/blob/v5.1.0/include/rtdebug.h).
+pub(crate) fn check_thread_context() -> Result<(), Error> {
+    debug_assert!(!in_interrupt(), "blocking call in interrupt context");
+    if in_interrupt() {
+        return Err(Error::Inval);
+    }
+    Ok(())
+}
+
+/// See [the c code](https://github.com/RT-Thread/rt-thread/blob/v5.1.0/libcpu/arm/cortex-a/interrupt.c).
+///
+/// Sets the handler of interrupt `irq` and clears its counter, returning the previous handler.
+/// The interrupt still has to be unmasked.
+/// Returns [`Error::Inval`] if `irq` is not below `IRQ_MAX`.
+pub fn install(irq: usize, name: &'static str, handler: Handler) -> Result<Option<Handler>, Error> {
+    set_handler(irq, name, Some(handler))
+}
+
+/// Removes the handler of interrupt `irq`, returning it.
+pub fn uninstall(irq: usize) -> Result<Option<Handler>, Error> {
+    set_handler(irq, "", None)
+}
+
+fn set_handler(
+    irq: usize,
+    name: &'static str,
+    handler: Option<Handler>,
+) -> Result<Option<Handler>, Error> {
+    IRQ_DESC.with(|descs| {
+        let desc = descs.get_mut(irq).ok_or(Error::Inval)?;
+        let old = desc.handler;
+        *desc = IrqDesc {
+            handler,
+            name,
+            counter: 0,
+        };
+        Ok(old)
+    })
+}
+
+/// See [the c code](https://github.com/RT-Thread/rt-thread/blob/v5.1.0/libcpu/arm/cortex-a/interrupt.c).
+#[inline]
+pub fn mask(irq: usize) {
+    cpu::interrupt_mask(irq)
+}
+
+/// See [the c code](https://github.com/RT-Thread/rt-thread/blob/v5.1.0/libcpu/arm/cortex-a/interrupt.c).
+#[inline]
+pub fn unmask(irq: usize) {
+    cpu::interrupt_unmask(irq)
+}
+
+/// Name and number of occurrences of interrupt `irq`, if it has a handler.
+pub fn info(irq: usize) -> Option<(&'static str, usize)> {
+    IRQ_DESC.with(|descs| {
+        let desc = descs.get(irq)?;
+        desc.handler.map(|_| (desc.name, desc.counter))
+    })
+}
+
+/// Calls the handler of interrupt `irq` between [`enter`] and [`leave`],
+/// for the interrupt entry of the port or the board.
+///
+/// Panics if the interrupt has no handler.
+pub fn dispatch(irq: usize) {
+    enter();
+    let handler = IRQ_DESC.with(|descs| {
+        descs.get_mut(irq).and_then(|desc| {
+            desc.counter += 1;
+            desc.handler
+        })
+    });
+    match handler {
+        Some(handler) => handler(irq),
+        None => panic!("unhandled IRQ {irq}"),
+    }
+    leave();
+}
+
+#[test]
+fn test_nest() {
+    use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+    static ENTERED: AtomicUsize = AtomicUsize::new(0);
+    let _lock = crate::test_lock();
+    enter_sethook(Some(|| {
+        ENTERED.fetch_add(1, Relaxed);
+    }));
+
+    assert!(!in_interrupt());
+    enter();
+    enter();
+    assert_eq!(get_nest(), 2);
+    leave();
+    assert!(in_interrupt());
+    leave();
+    assert!(!in_interrupt());
+    assert_eq!(ENTERED.load(Relaxed), 2);
+
+    enter_sethook(None);
+}
+
+#[test]
+fn test_dispatch() {
+    let _lock = crate::test_lock();
+    assert_eq!(install(IRQ_MAX, "none", &|_| {}).err(), Some(Error::Inval));
+
+    assert!(install(3, "three", &|irq| assert_eq!(irq, 3))
+        .unwrap()
+        .is_none());
+    dispatch(3);
+    dispatch(3);
+    assert_eq!(info(3), Some(("three", 2)));
+    assert!(!in_interrupt());
+
+    assert!(uninstall(3).unwrap().is_some());
+    assert_eq!(info(3), None);
+}
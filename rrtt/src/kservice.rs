@@ -0,0 +1,30 @@
+use crate::cpu::InterruptFreeCell;
+use core::fmt::{self, Write};
+
+static CONSOLE_OUTPUT: InterruptFreeCell<Option<fn(&str)>> = InterruptFreeCell::new(None);
+
+/// Sets where [`kprintf`] writes, like `rt_hw_console_output` of the board.
+///
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/kservice.c).
+pub fn console_set_output(output: Option<fn(&str)>) {
+    CONSOLE_OUTPUT.with(|console| *console = output);
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/kservice.c).
+///
+/// Does nothing until a console output is set.
+pub fn kprintf(args: fmt::Arguments) {
+    if let Some(output) = CONSOLE_OUTPUT.try_with(|output| *output).flatten() {
+        let _ = Console(output).write_fmt(args);
+    }
+}
+
+struct Console(fn(&str));
+
+impl Write for Console {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        (self.0)(s);
+        Ok(())
+    }
+}
@@ -1,10 +1,18 @@
 #![no_std]
 #![allow(unused)]
 
+#[cfg(feature = "mem-heap")]
+extern crate alloc;
+#[cfg(target_os = "linux")]
+extern crate std;
+
 use konst::{primitive::parse_usize, unwrap_ctx};
 
 const NAME_MAX: usize = 8;
+const ALIGN_SIZE: usize = 8;
 const PRIORITY_MAX: usize = unwrap_ctx!(parse_usize(env!("PRIORITY_MAX")));
+const TICK_PER_SECOND: usize = unwrap_ctx!(parse_usize(env!("TICK_PER_SECOND")));
+const IRQ_MAX: usize = unwrap_ctx!(parse_usize(env!("IRQ_MAX")));
 
 macro_rules! container_of {
     ($ptr:expr, $ty:ty, $field:ident) => {
@@ -12,10 +20,31 @@ macro_rules! container_of {
     };
 }
 
+mod atomic;
+mod clock;
 mod cpu;
+mod error;
+mod fault;
+mod idle;
+mod irq;
+mod kservice;
 mod list;
 mod object;
+mod port;
 mod scheduler;
+#[cfg(feature = "signals")]
+mod signal;
+#[cfg(all(test, target_os = "linux"))]
+mod sim;
 mod thread;
+mod timer;
+pub mod tls;
 
 type TodoType = ();
+
+/// Serializes the tests using the kernel globals.
+#[cfg(test)]
+fn test_lock() -> std::sync::MutexGuard<'static, ()> {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -46,6 +46,18 @@ impl Node {
         }
     }
 
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/include/rtservice.h).
+    #[inline]
+    pub fn insert_before(&mut self, n: &mut MaybeUninit<Self>) {
+        let n = unsafe { n.assume_init_mut() };
+        let n_ptr = unsafe { NonNull::new_unchecked(n) };
+        unsafe {
+            self.prev.as_mut().next = n_ptr;
+            n.prev = core::mem::replace(&mut self.prev, n_ptr);
+            n.next = NonNull::from(self);
+        }
+    }
+
     /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/include/rtservice.h#L82).
     #[inline]
     pub fn remove(&mut self) {
@@ -55,6 +67,12 @@ impl Node {
             self.init();
         }
     }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/include/rtservice.h).
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.next == NonNull::from(self)
+    }
 }
 
 impl Deref for Node {
@@ -1,43 +1,54 @@
-﻿use crate::{cpu, list, scheduler, TodoType, NAME_MAX};
+﻿use crate::{
+    cpu::{self, InterruptFreeCell},
+    list, scheduler,
+    thread::Thread,
+    TodoType, NAME_MAX,
+};
 use core::{
     ffi::CStr,
     mem::{size_of, MaybeUninit},
-    ptr::NonNull,
+    ptr::{addr_of, NonNull},
 };
 
 macro_rules! obj_info {
     ($ident:ident) => {
+        obj_info!($ident: TodoType)
+    };
+    ($ident:ident: $ty:ty) => {
         ObjectInformation {
             r#type: ObjectClassType::$ident,
             object_list: unsafe {
-                list::Node::new_empty(&OBJECT_CONTAINER[ObjIdx::$ident as usize].object_list)
+                list::Node::new_empty(addr_of!(
+                    (*OBJECT_CONTAINER.as_ptr())[ObjIdx::$ident as usize].object_list
+                ))
             },
-            object_size: size_of::<TodoType>(),
+            object_size: size_of::<$ty>(),
         }
     };
 }
 
 /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/object.c#L57).
-static mut OBJECT_CONTAINER: [ObjectInformation; ObjIdx::Unknown as usize] = [
-    obj_info!(Thread),
-    #[cfg(feature = "semaphore")]
-    obj_info!(Semaphore),
-    #[cfg(feature = "mutex")]
-    obj_info!(Mutex),
-    #[cfg(feature = "event")]
-    obj_info!(Event),
-    #[cfg(feature = "mailbox")]
-    obj_info!(MailBox),
-    #[cfg(feature = "message-queue")]
-    obj_info!(MessageQueue),
-    #[cfg(feature = "mem-heap")]
-    obj_info!(MemHeap),
-    #[cfg(feature = "mem-pool")]
-    obj_info!(MemPool),
-    #[cfg(feature = "device")]
-    obj_info!(Device),
-    obj_info!(Timer),
-];
+static OBJECT_CONTAINER: InterruptFreeCell<[ObjectInformation; ObjIdx::Unknown as usize]> =
+    InterruptFreeCell::new([
+        obj_info!(Thread: Thread),
+        #[cfg(feature = "semaphore")]
+        obj_info!(Semaphore),
+        #[cfg(feature = "mutex")]
+        obj_info!(Mutex),
+        #[cfg(feature = "event")]
+        obj_info!(Event),
+        #[cfg(feature = "mailbox")]
+        obj_info!(MailBox),
+        #[cfg(feature = "message-queue")]
+        obj_info!(MessageQueue),
+        #[cfg(feature = "mem-heap")]
+        obj_info!(MemHeap),
+        #[cfg(feature = "mem-pool")]
+        obj_info!(MemPool),
+        #[cfg(feature = "device")]
+        obj_info!(Device),
+        obj_info!(Timer),
+    ]);
 
 /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/include/rtdef.h#L334).
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -48,60 +59,119 @@ pub struct Object {
     /// type of kernel object
     r#type: u8,
     /// flag of kernel object
-    flag: u8,
+    pub(crate) flag: u8,
     /// list node of kernel object
     list: list::Node,
 }
 
 impl Object {
     /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/object.c#L201).
-    pub unsafe fn get_information(
+    ///
+    /// Calls `f` with the information of `r#type`, with interrupts disabled.
+    pub fn get_information<R>(
         r#type: ObjectClassType,
-    ) -> Option<&'static mut ObjectInformation> {
-        OBJECT_CONTAINER.iter_mut().find(|r| r.r#type == r#type)
+        f: impl FnOnce(&mut ObjectInformation) -> R,
+    ) -> Option<R> {
+        OBJECT_CONTAINER.with(|container| container.iter_mut().find(|r| r.r#type == r#type).map(f))
     }
 
     /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/object.c#L220).
     pub fn get_length(r#type: ObjectClassType) -> usize {
-        let Some(info) = (unsafe { Self::get_information(r#type) }) else { return 0; };
-        let _guard = cpu::InterruptFreeGuard::new();
-        info.object_list.into_iter().count()
+        Self::get_information(r#type, |info| info.object_list.into_iter().count()).unwrap_or(0)
     }
 
     /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/object.c#L248).
     pub fn get_pointers(r#type: ObjectClassType, buf: &mut [MaybeUninit<Object>]) -> usize {
-        let Some(info) = (unsafe { Self::get_information(r#type) }) else { return 0; };
-        let _guard = cpu::InterruptFreeGuard::new();
-        buf.iter_mut()
-            .zip(info.object_list.into_iter().rev())
-            .map(|(obj, node)| obj.write(unsafe { *(container_of!(node, Object, list)) }))
-            .count()
+        Self::get_information(r#type, |info| {
+            buf.iter_mut()
+                .zip(info.object_list.into_iter().rev())
+                .map(|(obj, node)| obj.write(unsafe { *(container_of!(node, Object, list)) }))
+                .count()
+        })
+        .unwrap_or(0)
     }
 
     /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/object.c#L289).
     pub fn init(object: &mut MaybeUninit<Self>, r#type: ObjectClassType, name: &str) {
-        let info = unsafe { Self::get_information(r#type) }.unwrap();
         {
             let _guard = scheduler::LockNestedGuard::new();
+            Self::get_information(r#type, |info| {
+                for member in info
+                    .object_list
+                    .into_iter()
+                    .map(|node| container_of!(node, Object, list))
+                {
+                    assert_ne!(member, object.as_ptr());
+                }
+            })
+            .unwrap();
+        }
+        let object = Self::write(
+            object.as_mut_ptr(),
+            r#type as u8 | ObjectClassType::Static as u8,
+        );
+        object.set_name(name);
+        // TODO HOOK
+        Self::get_information(r#type, |info| info.object_list.insert(&mut object.list));
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/object.c).
+    #[cfg(feature = "mem-heap")]
+    pub fn allocate(r#type: ObjectClassType, name: &str) -> Option<NonNull<Self>> {
+        let size = Self::get_information(r#type, |info| info.object_size).unwrap();
+        let ptr = unsafe { alloc::alloc::alloc_zeroed(object_layout(size)) };
+        let object = NonNull::new(ptr)?.cast::<Self>();
+        let obj = Self::write(object.as_ptr(), r#type as u8);
+        obj.set_name(name);
+        // TODO HOOK
+        Self::get_information(r#type, |info| info.object_list.insert(&mut obj.list));
+        Some(object)
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/object.c).
+    ///
+    /// # Safety
+    ///
+    /// `object` must come from [`Object::allocate`] and must not be used after this call.
+    #[cfg(feature = "mem-heap")]
+    pub unsafe fn delete(object: NonNull<Self>) {
+        let obj = &mut *object.as_ptr();
+        assert!(!obj.is_system_object());
+        let size = OBJECT_CONTAINER.with(|container| {
+            container
+                .iter()
+                .find(|info| info.r#type as u8 == obj.get_type())
+                .unwrap()
+                .object_size
+        });
+        // TODO HOOK
+
+        obj.r#type = ObjectClassType::Null as u8;
+        {
             let _guard = cpu::InterruptFreeGuard::new();
-            for member in info
-                .object_list
-                .into_iter()
-                .map(|node| container_of!(node, Object, list))
-            {
-                assert_ne!(member, object.as_ptr());
-            }
+            obj.list.remove();
+        }
+        alloc::alloc::dealloc(object.as_ptr().cast(), object_layout(size));
+    }
+
+    /// Writes an unnamed object with `r#type` to `ptr`, which may be uninitialized.
+    fn write(ptr: *mut Self, r#type: u8) -> &'static mut Self {
+        unsafe {
+            ptr.write(Self {
+                name: [0; NAME_MAX],
+                r#type,
+                flag: 0,
+                list: list::Node::new_empty(addr_of!((*ptr).list)),
+            });
+            &mut *ptr
         }
-        let object = unsafe { object.assume_init_mut() };
+    }
+
+    fn set_name(&mut self, name: &str) {
         let name = name.as_bytes();
-        let len = (object.name.len() - 1).min(name.len());
-        object.name[..len].copy_from_slice(&name[..len]);
-        object.name[len] = 0;
-        object.r#type = r#type as u8 | ObjectClassType::Static as u8;
-        object.flag = 0;
-        // TODO HOOK
-        let _guard = cpu::InterruptFreeGuard::new();
-        info.object_list.insert(&mut object.list);
+        let len = (self.name.len() - 1).min(name.len());
+        self.name[..len].copy_from_slice(&name[..len]);
+        self.name[len] = 0;
     }
 
     /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/object.c#L347).
@@ -114,6 +184,12 @@ impl Object {
         self.list.remove();
     }
 
+    /// Name of the object, truncated to [`NAME_MAX`] - 1 bytes.
+    pub fn name(&self) -> &str {
+        let name = unsafe { CStr::from_ptr(self.name.as_ptr().cast()) };
+        name.to_str().unwrap_or_default()
+    }
+
     /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/object.c#L464).
     #[inline]
     pub fn is_system_object(&self) -> bool {
@@ -128,19 +204,36 @@ impl Object {
 
     /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/object.c#L500).
     pub fn find(name: &str, r#type: ObjectClassType) -> Option<&'static mut Object> {
-        let Some(info) = (unsafe { Self::get_information(r#type) }) else { return None; };
-
         let _guard = scheduler::LockNestedGuard::new();
-        let _guard = cpu::InterruptFreeGuard::new();
-        info.object_list
-            .into_iter()
-            .map(|node| unsafe { &mut *container_of!(node, Object, list).cast_mut() })
-            .find(|obj| unsafe {
-                CStr::from_ptr(obj.name.as_ptr().cast()).to_bytes() == name.as_bytes()
-            })
+        Self::get_information(r#type, |info| {
+            info.object_list
+                .into_iter()
+                .map(|node| unsafe { &mut *container_of!(node, Object, list).cast_mut() })
+                .find(|obj| unsafe {
+                    CStr::from_ptr(obj.name.as_ptr().cast()).to_bytes() == name.as_bytes()
+                })
+        })
+        .flatten()
     }
 }
 
+/// Forgets all objects, for tests booting the kernel more than once.
+#[cfg(test)]
+pub(crate) fn reset() {
+    OBJECT_CONTAINER.with(|container| {
+        for info in container {
+            info.object_list.init();
+        }
+    });
+}
+
+/// Memory layout of an object allocated from the kernel heap.
+#[cfg(feature = "mem-heap")]
+#[inline]
+fn object_layout(object_size: usize) -> core::alloc::Layout {
+    core::alloc::Layout::from_size_align(object_size, crate::ALIGN_SIZE).unwrap()
+}
+
 /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/include/rtdef.h#L344).
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(u8)]
@@ -221,62 +314,66 @@ enum ObjIdx {
 
 #[test]
 fn test_get_information() {
-    unsafe {
-        assert_eq!(
-            [
-                None,
-                Some(&mut OBJECT_CONTAINER[ObjIdx::Thread as usize]),
-                #[cfg(feature = "semaphore")]
-                Some(&mut OBJECT_CONTAINER[ObjIdx::Semaphore as usize]),
-                #[cfg(feature = "mutex")]
-                Some(&mut OBJECT_CONTAINER[ObjIdx::Mutex as usize]),
-                #[cfg(feature = "event")]
-                Some(&mut OBJECT_CONTAINER[ObjIdx::Event as usize]),
-                #[cfg(feature = "mailbox")]
-                Some(&mut OBJECT_CONTAINER[ObjIdx::MailBox as usize]),
-                #[cfg(feature = "message-queue")]
-                Some(&mut OBJECT_CONTAINER[ObjIdx::MessageQueue as usize]),
-                #[cfg(feature = "mem-heap")]
-                Some(&mut OBJECT_CONTAINER[ObjIdx::MemHeap as usize]),
-                #[cfg(feature = "mem-pool")]
-                Some(&mut OBJECT_CONTAINER[ObjIdx::MemPool as usize]),
-                #[cfg(feature = "device")]
-                Some(&mut OBJECT_CONTAINER[ObjIdx::Device as usize]),
-                Some(&mut OBJECT_CONTAINER[ObjIdx::Timer as usize]),
-                None,
-                None
-            ],
-            [
-                Object::get_information(ObjectClassType::Null),
-                Object::get_information(ObjectClassType::Thread),
-                #[cfg(feature = "semaphore")]
-                Object::get_information(ObjectClassType::Semaphore),
-                #[cfg(feature = "mutex")]
-                Object::get_information(ObjectClassType::Mutex),
-                #[cfg(feature = "event")]
-                Object::get_information(ObjectClassType::Event),
-                #[cfg(feature = "mailbox")]
-                Object::get_information(ObjectClassType::MailBox),
-                #[cfg(feature = "message-queue")]
-                Object::get_information(ObjectClassType::MessageQueue),
-                #[cfg(feature = "mem-heap")]
-                Object::get_information(ObjectClassType::MemHeap),
-                #[cfg(feature = "mem-pool")]
-                Object::get_information(ObjectClassType::MemPool),
-                #[cfg(feature = "device")]
-                Object::get_information(ObjectClassType::Device),
-                Object::get_information(ObjectClassType::Timer),
-                Object::get_information(ObjectClassType::Unknown),
-                Object::get_information(ObjectClassType::Static),
-            ]
-        );
-    }
+    use ObjectClassType as Ty;
+
+    let r#type = |r#type| Object::get_information(r#type, |info| info.r#type);
+    assert_eq!(
+        [
+            None,
+            Some(Ty::Thread),
+            #[cfg(feature = "semaphore")]
+            Some(Ty::Semaphore),
+            #[cfg(feature = "mutex")]
+            Some(Ty::Mutex),
+            #[cfg(feature = "event")]
+            Some(Ty::Event),
+            #[cfg(feature = "mailbox")]
+            Some(Ty::MailBox),
+            #[cfg(feature = "message-queue")]
+            Some(Ty::MessageQueue),
+            #[cfg(feature = "mem-heap")]
+            Some(Ty::MemHeap),
+            #[cfg(feature = "mem-pool")]
+            Some(Ty::MemPool),
+            #[cfg(feature = "device")]
+            Some(Ty::Device),
+            Some(Ty::Timer),
+            None,
+            None
+        ],
+        [
+            r#type(Ty::Null),
+            r#type(Ty::Thread),
+            #[cfg(feature = "semaphore")]
+            r#type(Ty::Semaphore),
+            #[cfg(feature = "mutex")]
+            r#type(Ty::Mutex),
+            #[cfg(feature = "event")]
+            r#type(Ty::Event),
+            #[cfg(feature = "mailbox")]
+            r#type(Ty::MailBox),
+            #[cfg(feature = "message-queue")]
+            r#type(Ty::MessageQueue),
+            #[cfg(feature = "mem-heap")]
+            r#type(Ty::MemHeap),
+            #[cfg(feature = "mem-pool")]
+            r#type(Ty::MemPool),
+            #[cfg(feature = "device")]
+            r#type(Ty::Device),
+            r#type(Ty::Timer),
+            r#type(Ty::Unknown),
+            r#type(Ty::Static),
+        ]
+    );
 }
 
 #[test]
 fn test_modify() {
     use ObjectClassType as Ty;
 
+    let _lock = crate::test_lock();
+    reset();
+
     assert_eq!(0, Object::get_length(Ty::Null));
     assert_eq!(0, Object::get_length(Ty::Thread));
 
@@ -305,4 +402,5 @@ fn test_modify() {
 
     let thread1 = Object::find("thread1", Ty::Thread);
     unsafe { assert_eq!(Some(threads[1].assume_init_mut()), thread1) };
+    reset();
 }
@@ -0,0 +1,92 @@
+use core::ptr::NonNull;
+
+/// What the kernel needs from an architecture,
+/// see [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/include/rthw.h).
+///
+/// Kernel modules only go through [`crate::cpu`], which forwards to the [`Current`] port.
+pub trait Port {
+    /// Bytes of a thread stack the port may take:
+    /// the frame of a switched-out thread, along with what an interrupt taken on it pushes.
+    const STACK_MIN: usize;
+
+    /// Disables interrupts and returns the previous interrupt state.
+    fn interrupt_disable() -> usize;
+
+    /// Restores the interrupt state returned by [`Port::interrupt_disable`].
+    fn interrupt_enable(level: usize);
+
+    /// Disables interrupt `irq` at the interrupt controller.
+    fn interrupt_mask(irq: usize);
+
+    /// Enables interrupt `irq` at the interrupt controller.
+    fn interrupt_unmask(irq: usize);
+
+    /// Builds the initial frame of a thread below `stack_addr` and returns the new stack pointer.
+    ///
+    /// The thread starts at `entry(parameter)` and goes to `exit` when `entry` returns.
+    ///
+    /// # Safety
+    ///
+    /// `stack_addr` must point into a stack large enough for the frame.
+    unsafe fn stack_init(
+        entry: extern "C" fn(usize),
+        parameter: usize,
+        stack_addr: *mut u8,
+        exit: extern "C" fn() -> !,
+    ) -> *mut u8;
+
+    /// Saves the current context to `*from` and switches to the context at `*to`.
+    ///
+    /// # Safety
+    ///
+    /// `*to` must be a stack pointer saved by the port or returned by [`Port::stack_init`].
+    unsafe fn context_switch(from: *mut NonNull<usize>, to: *mut NonNull<usize>);
+
+    /// Switches to the context at `*to` without saving the current one.
+    ///
+    /// # Safety
+    ///
+    /// See [`Port::context_switch`].
+    unsafe fn context_switch_to(to: *mut NonNull<usize>);
+
+    /// Requests a switch from `*from` to `*to` once the outermost interrupt returns.
+    ///
+    /// # Safety
+    ///
+    /// See [`Port::context_switch`].
+    unsafe fn context_switch_interrupt(from: *mut NonNull<usize>, to: *mut NonNull<usize>);
+
+    /// Starts the tick source, whose interrupt calls [`crate::clock::tick_increase`]
+    /// `tick_per_second` times a second.
+    fn tick_init(tick_per_second: usize);
+
+    /// Reads a free-running counter, finer than the tick, that never goes back.
+    fn cycle_get() -> u64;
+
+    /// Frequency of [`Port::cycle_get`] in Hz.
+    fn cycle_frequency() -> u64;
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(target_arch = "arm", target_os = "none"))] {
+        mod cortex_m;
+        /// The port of the target architecture.
+        pub type Current = cortex_m::CortexM;
+    } else if #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_os = "none"))] {
+        mod riscv;
+        /// The port of the target architecture.
+        pub type Current = riscv::RiscV;
+    } else if #[cfg(all(target_arch = "aarch64", target_os = "none"))] {
+        mod aarch64;
+        /// The port of the target architecture.
+        pub type Current = aarch64::AArch64;
+    } else if #[cfg(target_os = "linux")] {
+        pub(crate) mod host;
+        /// The port of the target architecture.
+        pub type Current = host::Host;
+    } else {
+        mod null;
+        /// The port of the target architecture.
+        pub type Current = null::Null;
+    }
+}
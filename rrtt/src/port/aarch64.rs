@@ -0,0 +1,486 @@
+//! See [the c code](https://github.com/RT-Thread/rt-thread/tree/v5.1.0/libcpu/aarch64/common).
+//!
+//! The kernel and the threads run at EL1 on `SP_EL1`.
+//! A switch saves `x19-x30`, `DAIF` and the stack pointer, like a function call.
+//! An IRQ saves the caller-saved registers on the stack of the interrupted thread,
+//! and a switch requested in it is made by the IRQ exit calling [`Port::context_switch`],
+//! so the thread resumes in the exit path and returns with `eret`.
+//!
+//! The virtual timer of the generic timer drives the tick, which also works in a partition under a hypervisor.
+//! Interrupts are acknowledged at a GICv2 at `GICD_BASE` and `GICC_BASE`,
+//! whose distributor and CPU interface are enabled by the board,
+//! and the ones other than the tick go to [`crate::irq::dispatch`],
+//! whose table covers every interrupt ID of the GIC unless `IRQ_MAX` is set lower.
+//! With `neon`, `d8-d15` are saved on switch and `q0-q31` with `FPCR`/`FPSR` on IRQ.
+
+use super::Port;
+use crate::{
+    clock,
+    fault::{self, Fault},
+    irq,
+};
+use core::{
+    arch::{asm, global_asm},
+    mem::size_of,
+    ptr::{null_mut, NonNull},
+};
+use konst::{primitive::parse_usize, unwrap_ctx};
+
+/// `DAIF` bits masked by [`Port::interrupt_disable`], IRQ and FIQ
+const DAIF_IF: usize = 0b11 << 6;
+
+const CNTV_CTL_ENABLE: usize = 1 << 0;
+
+const GICD_BASE: usize = unwrap_ctx!(parse_usize(env!("GICD_BASE")));
+const GICC_BASE: usize = unwrap_ctx!(parse_usize(env!("GICC_BASE")));
+/// Interrupt set-enable registers
+const GICD_ISENABLER: *mut u32 = (GICD_BASE + 0x100) as *mut u32;
+/// Interrupt clear-enable registers
+const GICD_ICENABLER: *mut u32 = (GICD_BASE + 0x180) as *mut u32;
+/// Interrupt acknowledge register
+const GICC_IAR: *const u32 = (GICC_BASE + 0x0c) as *const u32;
+/// End of interrupt register
+const GICC_EOIR: *mut u32 = (GICC_BASE + 0x10) as *mut u32;
+/// First of the special interrupt IDs, which include the spurious 1023
+const GIC_SPECIAL: usize = 1020;
+/// PPI of the virtual timer
+const IRQ_VTIMER: usize = 27;
+
+/// Words of a switch frame: `x19-x30`, `DAIF` and a pad, then `d8-d15` with `neon`.
+#[cfg(target_feature = "neon")]
+const FRAME_WORDS: usize = 14 + 8;
+#[cfg(not(target_feature = "neon"))]
+const FRAME_WORDS: usize = 14;
+const FRAME_SIZE: usize = FRAME_WORDS * size_of::<usize>();
+/// Bytes pushed on IRQ: `x0-x18`, `x29`, `x30`, `ELR`, `SPSR` and a pad, then `q0-q31` with `FPCR`/`FPSR` with `neon`.
+#[cfg(target_feature = "neon")]
+const IRQ_FRAME_SIZE: usize = 24 * 8 + 34 * 16;
+#[cfg(not(target_feature = "neon"))]
+const IRQ_FRAME_SIZE: usize = 24 * 8;
+const FRAME_X19: usize = 0;
+const FRAME_X20: usize = 1;
+const FRAME_X21: usize = 2;
+const FRAME_X29: usize = 10;
+const FRAME_X30: usize = 11;
+const FRAME_DAIF: usize = 12;
+
+static mut INTERRUPT_FROM_THREAD: *mut NonNull<usize> = null_mut();
+static mut INTERRUPT_TO_THREAD: *mut NonNull<usize> = null_mut();
+static mut THREAD_SWITCH_INTERRUPT_FLAG: usize = 0;
+static mut TICK_CYCLES: usize = 0;
+
+extern "C" {
+    fn rrtt_context_switch(from: *mut NonNull<usize>, to: *mut NonNull<usize>);
+    fn rrtt_context_switch_to(to: *mut NonNull<usize>);
+    fn rrtt_thread_start();
+}
+
+/// AArch64 port at EL1.
+pub struct AArch64;
+
+impl Port for AArch64 {
+    /// An IRQ frame and the switch frame made in its exit, aligned to 16 bytes.
+    /// IRQs do not nest.
+    const STACK_MIN: usize = IRQ_FRAME_SIZE + FRAME_SIZE + 16;
+
+    #[inline]
+    fn interrupt_disable() -> usize {
+        let daif: usize;
+        unsafe { asm!("mrs {}, daif", "msr daifset, #3", out(reg) daif, options(nostack)) };
+        daif
+    }
+
+    /// Restores the I and F bits only, so the D and A masks stay as they are.
+    #[inline]
+    fn interrupt_enable(level: usize) {
+        unsafe {
+            asm!(
+                "mrs {daif}, daif",
+                "bic {daif}, {daif}, #{mask}",
+                "orr {daif}, {daif}, {level}",
+                "msr daif, {daif}",
+                daif = out(reg) _,
+                level = in(reg) level & DAIF_IF,
+                mask = const DAIF_IF,
+                options(nostack),
+            )
+        };
+    }
+
+    #[inline]
+    fn interrupt_mask(irq: usize) {
+        unsafe { GICD_ICENABLER.add(irq / 32).write_volatile(1 << (irq % 32)) };
+    }
+
+    #[inline]
+    fn interrupt_unmask(irq: usize) {
+        unsafe { GICD_ISENABLER.add(irq / 32).write_volatile(1 << (irq % 32)) };
+    }
+
+    unsafe fn stack_init(
+        entry: extern "C" fn(usize),
+        parameter: usize,
+        stack_addr: *mut u8,
+        exit: extern "C" fn() -> !,
+    ) -> *mut u8 {
+        let top = (stack_addr as usize + size_of::<usize>()) & !15;
+        let frame = (top - FRAME_SIZE) as *mut usize;
+        for i in 0..FRAME_WORDS {
+            frame.add(i).write(0xdead_beef);
+        }
+        // `rrtt_thread_start` calls `x20(x19)` and returns to `x21`
+        frame.add(FRAME_X19).write(parameter);
+        frame.add(FRAME_X20).write(entry as usize);
+        frame.add(FRAME_X21).write(exit as usize);
+        frame.add(FRAME_X29).write(0);
+        frame
+            .add(FRAME_X30)
+            .write(rrtt_thread_start as *const () as usize);
+        // interrupts enabled, debug and SError masked as they are now
+        let daif: usize;
+        asm!("mrs {}, daif", out(reg) daif, options(nomem, nostack));
+        frame.add(FRAME_DAIF).write(daif & !DAIF_IF);
+        frame.cast()
+    }
+
+    #[inline]
+    unsafe fn context_switch(from: *mut NonNull<usize>, to: *mut NonNull<usize>) {
+        rrtt_context_switch(from, to)
+    }
+
+    #[inline]
+    unsafe fn context_switch_to(to: *mut NonNull<usize>) {
+        rrtt_context_switch_to(to)
+    }
+
+    #[inline]
+    unsafe fn context_switch_interrupt(from: *mut NonNull<usize>, to: *mut NonNull<usize>) {
+        if THREAD_SWITCH_INTERRUPT_FLAG != 1 {
+            THREAD_SWITCH_INTERRUPT_FLAG = 1;
+            INTERRUPT_FROM_THREAD = from;
+        }
+        INTERRUPT_TO_THREAD = to;
+    }
+
+    fn tick_init(tick_per_second: usize) {
+        unsafe {
+            let freq: usize;
+            asm!("mrs {}, cntfrq_el0", out(reg) freq);
+            TICK_CYCLES = freq / tick_per_second;
+            asm!(
+                "msr cntv_tval_el0, {}",
+                "msr cntv_ctl_el0, {}",
+                "isb",
+                in(reg) TICK_CYCLES,
+                in(reg) CNTV_CTL_ENABLE,
+            );
+        }
+        Self::interrupt_unmask(IRQ_VTIMER);
+    }
+
+    /// The virtual count of the generic timer.
+    #[inline]
+    fn cycle_get() -> u64 {
+        let count: u64;
+        unsafe { asm!("isb", "mrs {}, cntvct_el0", out(reg) count, options(nostack)) };
+        count
+    }
+
+    #[inline]
+    fn cycle_frequency() -> u64 {
+        let freq: u64;
+        unsafe { asm!("mrs {}, cntfrq_el0", out(reg) freq, options(nomem, nostack)) };
+        freq
+    }
+}
+
+/// Called by the IRQ entry on the stack of the interrupted thread.
+extern "C" fn irq_handler() {
+    let iar = unsafe { GICC_IAR.read_volatile() };
+    match iar as usize & 0x3ff {
+        GIC_SPECIAL.. => return,
+        IRQ_VTIMER => {
+            irq::enter();
+            unsafe { asm!("msr cntv_tval_el0, {}", in(reg) TICK_CYCLES) };
+            clock::tick_increase();
+            irq::leave();
+        }
+        irq => irq::dispatch(irq),
+    }
+    unsafe { GICC_EOIR.write_volatile(iar) };
+}
+
+/// Called by the vectors of the exceptions the kernel does not handle.
+extern "C" fn trap_handler(
+    kind: usize,
+    esr: usize,
+    elr: usize,
+    fp: usize,
+    lr: usize,
+    sp: usize,
+) -> ! {
+    const KINDS: [&str; 4] = [
+        "unhandled synchronous exception",
+        "unhandled IRQ",
+        "unhandled FIQ",
+        "unhandled SError",
+    ];
+
+    let (far, spsr): (usize, usize);
+    unsafe { asm!("mrs {}, far_el1", "mrs {}, spsr_el1", out(reg) far, out(reg) spsr) };
+    fault::handle(&Fault {
+        kind: KINDS[kind & 3],
+        registers: &[
+            ("esr", esr),
+            ("elr", elr),
+            ("far", far),
+            ("spsr", spsr),
+            ("x29", fp),
+            ("x30", lr),
+            ("sp", sp),
+        ],
+        frame_pointer: fp,
+    })
+}
+
+#[cfg(target_feature = "neon")]
+macro_rules! save_callee_fp {
+    () => {
+        "   stp     d8, d9, [sp, #14 * 8]
+            stp     d10, d11, [sp, #16 * 8]
+            stp     d12, d13, [sp, #18 * 8]
+            stp     d14, d15, [sp, #20 * 8]"
+    };
+}
+
+#[cfg(target_feature = "neon")]
+macro_rules! restore_callee_fp {
+    () => {
+        "   ldp     d8, d9, [sp, #14 * 8]
+            ldp     d10, d11, [sp, #16 * 8]
+            ldp     d12, d13, [sp, #18 * 8]
+            ldp     d14, d15, [sp, #20 * 8]"
+    };
+}
+
+#[cfg(target_feature = "neon")]
+macro_rules! save_caller_fp {
+    () => {
+        "   sub     sp, sp, #34 * 16
+            stp     q0, q1, [sp, #0 * 32 + 16]
+            stp     q2, q3, [sp, #1 * 32 + 16]
+            stp     q4, q5, [sp, #2 * 32 + 16]
+            stp     q6, q7, [sp, #3 * 32 + 16]
+            stp     q8, q9, [sp, #4 * 32 + 16]
+            stp     q10, q11, [sp, #5 * 32 + 16]
+            stp     q12, q13, [sp, #6 * 32 + 16]
+            stp     q14, q15, [sp, #7 * 32 + 16]
+            stp     q16, q17, [sp, #8 * 32 + 16]
+            stp     q18, q19, [sp, #9 * 32 + 16]
+            stp     q20, q21, [sp, #10 * 32 + 16]
+            stp     q22, q23, [sp, #11 * 32 + 16]
+            stp     q24, q25, [sp, #12 * 32 + 16]
+            stp     q26, q27, [sp, #13 * 32 + 16]
+            stp     q28, q29, [sp, #14 * 32 + 16]
+            stp     q30, q31, [sp, #15 * 32 + 16]
+            mrs     x9, fpcr
+            mrs     x10, fpsr
+            stp     x9, x10, [sp]"
+    };
+}
+
+#[cfg(target_feature = "neon")]
+macro_rules! restore_caller_fp {
+    () => {
+        "   ldp     x9, x10, [sp]
+            msr     fpcr, x9
+            msr     fpsr, x10
+            ldp     q0, q1, [sp, #0 * 32 + 16]
+            ldp     q2, q3, [sp, #1 * 32 + 16]
+            ldp     q4, q5, [sp, #2 * 32 + 16]
+            ldp     q6, q7, [sp, #3 * 32 + 16]
+            ldp     q8, q9, [sp, #4 * 32 + 16]
+            ldp     q10, q11, [sp, #5 * 32 + 16]
+            ldp     q12, q13, [sp, #6 * 32 + 16]
+            ldp     q14, q15, [sp, #7 * 32 + 16]
+            ldp     q16, q17, [sp, #8 * 32 + 16]
+            ldp     q18, q19, [sp, #9 * 32 + 16]
+            ldp     q20, q21, [sp, #10 * 32 + 16]
+            ldp     q22, q23, [sp, #11 * 32 + 16]
+            ldp     q24, q25, [sp, #12 * 32 + 16]
+            ldp     q26, q27, [sp, #13 * 32 + 16]
+            ldp     q28, q29, [sp, #14 * 32 + 16]
+            ldp     q30, q31, [sp, #15 * 32 + 16]
+            add     sp, sp, #34 * 16"
+    };
+}
+
+/// Gives EL1 access to FP/SIMD.
+#[cfg(target_feature = "neon")]
+macro_rules! enable_fp {
+    () => {
+        "   mrs     x9, cpacr_el1
+            orr     x9, x9, #(3 << 20)
+            msr     cpacr_el1, x9
+            isb"
+    };
+}
+
+#[cfg(not(target_feature = "neon"))]
+macro_rules! save_callee_fp {
+    () => {
+        ""
+    };
+}
+
+#[cfg(not(target_feature = "neon"))]
+use {
+    save_callee_fp as restore_callee_fp, save_callee_fp as save_caller_fp,
+    save_callee_fp as restore_caller_fp, save_callee_fp as enable_fp,
+};
+
+/// A vector branching to `trap_handler` with the kind of exception.
+macro_rules! unhandled_vector {
+    ($kind:literal) => {
+        concat!(
+            ".balign 0x80\n",
+            "   mov     x0, #",
+            $kind,
+            "\n",
+            "   mrs     x1, esr_el1\n",
+            "   mrs     x2, elr_el1\n",
+            "   mov     x3, x29\n",
+            "   mov     x4, x30\n",
+            "   mov     x5, sp\n",
+            "   b       {trap_handler}\n",
+        )
+    };
+}
+
+global_asm!(
+    ".section .text.rrtt_context_switch",
+    ".global rrtt_context_switch",
+    ".balign 4",
+    "rrtt_context_switch:",
+    "   sub     sp, sp, #{frame_size}",
+    "   stp     x19, x20, [sp, #0 * 8]",
+    "   stp     x21, x22, [sp, #2 * 8]",
+    "   stp     x23, x24, [sp, #4 * 8]",
+    "   stp     x25, x26, [sp, #6 * 8]",
+    "   stp     x27, x28, [sp, #8 * 8]",
+    "   stp     x29, x30, [sp, #10 * 8]",
+    "   mrs     x9, daif",
+    "   str     x9, [sp, #12 * 8]",
+    save_callee_fp!(),
+    "   mov     x9, sp",
+    "   str     x9, [x0]",
+    "   ldr     x9, [x1]",
+    "   mov     sp, x9",
+    "rrtt_restore_switch_frame:",
+    restore_callee_fp!(),
+    "   ldp     x19, x20, [sp, #0 * 8]",
+    "   ldp     x21, x22, [sp, #2 * 8]",
+    "   ldp     x23, x24, [sp, #4 * 8]",
+    "   ldp     x25, x26, [sp, #6 * 8]",
+    "   ldp     x27, x28, [sp, #8 * 8]",
+    "   ldp     x29, x30, [sp, #10 * 8]",
+    "   ldr     x9, [sp, #12 * 8]",
+    "   msr     daif, x9",
+    "   add     sp, sp, #{frame_size}",
+    "   ret",
+    //
+    ".global rrtt_context_switch_to",
+    "rrtt_context_switch_to:",
+    enable_fp!(),
+    "   adrp    x9, rrtt_vectors",
+    "   add     x9, x9, :lo12:rrtt_vectors",
+    "   msr     vbar_el1, x9",
+    "   isb",
+    "   ldr     x9, [x0]",
+    "   mov     sp, x9",
+    "   b       rrtt_restore_switch_frame",
+    //
+    ".global rrtt_thread_start",
+    "rrtt_thread_start:",
+    "   mov     x0, x19",
+    "   mov     x30, x21",
+    "   br      x20",
+    //
+    "rrtt_irq_entry:",
+    "   sub     sp, sp, #24 * 8",
+    "   stp     x0, x1, [sp, #0 * 8]",
+    "   stp     x2, x3, [sp, #2 * 8]",
+    "   stp     x4, x5, [sp, #4 * 8]",
+    "   stp     x6, x7, [sp, #6 * 8]",
+    "   stp     x8, x9, [sp, #8 * 8]",
+    "   stp     x10, x11, [sp, #10 * 8]",
+    "   stp     x12, x13, [sp, #12 * 8]",
+    "   stp     x14, x15, [sp, #14 * 8]",
+    "   stp     x16, x17, [sp, #16 * 8]",
+    "   stp     x18, x29, [sp, #18 * 8]",
+    "   mrs     x9, elr_el1",
+    "   stp     x30, x9, [sp, #20 * 8]",
+    "   mrs     x9, spsr_el1",
+    "   str     x9, [sp, #22 * 8]",
+    save_caller_fp!(),
+    "   bl      {irq_handler}",
+    // switch if requested by the handler, coming back here once switched to again
+    "   adrp    x0, {flag}",
+    "   add     x0, x0, :lo12:{flag}",
+    "   ldr     x1, [x0]",
+    "   cbz     x1, 1f",
+    "   str     xzr, [x0]",
+    "   adrp    x0, {from}",
+    "   ldr     x0, [x0, :lo12:{from}]",
+    "   adrp    x1, {to}",
+    "   ldr     x1, [x1, :lo12:{to}]",
+    "   bl      rrtt_context_switch",
+    "1:",
+    restore_caller_fp!(),
+    "   ldr     x9, [sp, #22 * 8]",
+    "   msr     spsr_el1, x9",
+    "   ldp     x30, x9, [sp, #20 * 8]",
+    "   msr     elr_el1, x9",
+    "   ldp     x0, x1, [sp, #0 * 8]",
+    "   ldp     x2, x3, [sp, #2 * 8]",
+    "   ldp     x4, x5, [sp, #4 * 8]",
+    "   ldp     x6, x7, [sp, #6 * 8]",
+    "   ldp     x8, x9, [sp, #8 * 8]",
+    "   ldp     x10, x11, [sp, #10 * 8]",
+    "   ldp     x12, x13, [sp, #12 * 8]",
+    "   ldp     x14, x15, [sp, #14 * 8]",
+    "   ldp     x16, x17, [sp, #16 * 8]",
+    "   ldp     x18, x29, [sp, #18 * 8]",
+    "   add     sp, sp, #24 * 8",
+    "   eret",
+    //
+    ".section .text.rrtt_vectors",
+    ".balign 0x800",
+    "rrtt_vectors:",
+    // current EL with SP_EL0
+    unhandled_vector!("0"),
+    unhandled_vector!("1"),
+    unhandled_vector!("2"),
+    unhandled_vector!("3"),
+    // current EL with SP_ELx
+    unhandled_vector!("0"),
+    ".balign 0x80",
+    "   b       rrtt_irq_entry",
+    unhandled_vector!("2"),
+    unhandled_vector!("3"),
+    // lower EL, AArch64 and AArch32
+    unhandled_vector!("0"),
+    unhandled_vector!("1"),
+    unhandled_vector!("2"),
+    unhandled_vector!("3"),
+    unhandled_vector!("0"),
+    unhandled_vector!("1"),
+    unhandled_vector!("2"),
+    unhandled_vector!("3"),
+    frame_size = const FRAME_SIZE,
+    irq_handler = sym irq_handler,
+    trap_handler = sym trap_handler,
+    flag = sym THREAD_SWITCH_INTERRUPT_FLAG,
+    from = sym INTERRUPT_FROM_THREAD,
+    to = sym INTERRUPT_TO_THREAD,
+);
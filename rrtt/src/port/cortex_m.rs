@@ -0,0 +1,557 @@
+//! See [the c code](https://github.com/RT-Thread/rt-thread/tree/v5.1.0/libcpu/arm).
+//!
+//! Threads run on PSP and switch in PendSV at the lowest priority,
+//! so a switch requested in a thread or an interrupt happens once interrupts are enabled again
+//! and no other exception is active.
+//! `PendSV`, `SysTick`, `HardFault` and `DefaultHandler` are exported under the names of the `cortex-m-rt` vector table,
+//! the latter passing the device interrupts to [`crate::irq::dispatch`].
+//!
+//! SysTick and cycles run on the core clock at env `CPU_FREQUENCY`.
+//! Cycles are counted by the DWT, started on the first read and extended to 64 bits at each tick,
+//! or on ARMv6-M without one by SysTick from the tick count.
+//! Before [`Port::tick_init`], DWT wraps are only seen if cycles are read at least once every 2^32 cycles.
+//!
+//! On M4F/M7 (`eabihf`) the FPU is enabled with automatic lazy stacking:
+//! the hardware only saves `s0-s15` for threads that used the FPU,
+//! and PendSV saves `s16-s31` for them, as told by bit 4 of `EXC_RETURN`.
+
+use super::Port;
+use crate::fault::{self, Fault};
+use core::{
+    arch::{asm, global_asm},
+    mem::size_of,
+    ptr::{null_mut, NonNull},
+};
+use konst::{primitive::parse_usize, unwrap_ctx};
+
+/// Core clock in Hz, counted by SysTick and the DWT
+const CPU_FREQUENCY: usize = unwrap_ctx!(parse_usize(env!("CPU_FREQUENCY")));
+
+/// Interrupt control and state register
+const ICSR: *mut u32 = 0xE000_ED04 as *mut u32;
+/// Vector table offset register
+const VTOR: *const u32 = 0xE000_ED08 as *const u32;
+/// System handler priority register 3, for PendSV and SysTick
+const SHPR3: *mut u32 = 0xE000_ED20 as *mut u32;
+/// Coprocessor access control register
+#[cfg(target_abi = "eabihf")]
+const CPACR: *mut u32 = 0xE000_ED88 as *mut u32;
+/// Floating-point context control register
+#[cfg(target_abi = "eabihf")]
+const FPCCR: *mut u32 = 0xE000_EF34 as *mut u32;
+/// Configurable fault status register
+#[cfg(target_feature = "thumb2")]
+const CFSR: *const u32 = 0xE000_ED28 as *const u32;
+/// HardFault status register
+#[cfg(target_feature = "thumb2")]
+const HFSR: *const u32 = 0xE000_ED2C as *const u32;
+/// MemManage fault address register
+#[cfg(target_feature = "thumb2")]
+const MMFAR: *const u32 = 0xE000_ED34 as *const u32;
+/// BusFault address register
+#[cfg(target_feature = "thumb2")]
+const BFAR: *const u32 = 0xE000_ED38 as *const u32;
+/// NVIC interrupt set-enable registers
+const NVIC_ISER: *mut u32 = 0xE000_E100 as *mut u32;
+/// NVIC interrupt clear-enable registers
+const NVIC_ICER: *mut u32 = 0xE000_E180 as *mut u32;
+/// SysTick control and status register
+const SYST_CSR: *mut u32 = 0xE000_E010 as *mut u32;
+/// SysTick reload value register
+const SYST_RVR: *mut u32 = 0xE000_E014 as *mut u32;
+/// SysTick current value register
+const SYST_CVR: *mut u32 = 0xE000_E018 as *mut u32;
+
+/// Debug exception and monitor control register
+#[cfg(target_feature = "thumb2")]
+const DEMCR: *mut u32 = 0xE000_EDFC as *mut u32;
+/// DWT control register
+#[cfg(target_feature = "thumb2")]
+const DWT_CTRL: *mut u32 = 0xE000_1000 as *mut u32;
+/// DWT cycle count register
+#[cfg(target_feature = "thumb2")]
+const DWT_CYCCNT: *mut u32 = 0xE000_1004 as *mut u32;
+/// DWT lock access register, only implemented on M7
+#[cfg(target_feature = "thumb2")]
+const DWT_LAR: *mut u32 = 0xE000_1FB0 as *mut u32;
+
+const PENDSVSET: u32 = 1 << 28;
+#[cfg(not(target_feature = "thumb2"))]
+const PENDSTSET: u32 = 1 << 26;
+#[cfg(target_feature = "thumb2")]
+const DEMCR_TRCENA: u32 = 1 << 24;
+#[cfg(target_feature = "thumb2")]
+const DWT_CTRL_CYCCNTENA: u32 = 1 << 0;
+#[cfg(target_feature = "thumb2")]
+const DWT_LAR_KEY: u32 = 0xC5AC_CE55;
+const PENDSV_SYSTICK_PRI: u32 = 0xFFFF_0000;
+const XPSR_T: u32 = 1 << 24;
+
+static mut INTERRUPT_FROM_THREAD: *mut NonNull<usize> = null_mut();
+static mut INTERRUPT_TO_THREAD: *mut NonNull<usize> = null_mut();
+static mut THREAD_SWITCH_INTERRUPT_FLAG: u32 = 0;
+/// Wraps of `DWT_CYCCNT` and its last value read
+#[cfg(target_feature = "thumb2")]
+static mut CYCLE_HIGH: u32 = 0;
+#[cfg(target_feature = "thumb2")]
+static mut CYCLE_LAST: u32 = 0;
+
+/// Registers pushed by the hardware on exception entry.
+#[repr(C)]
+struct ExceptionStackFrame {
+    r0: u32,
+    r1: u32,
+    r2: u32,
+    r3: u32,
+    r12: u32,
+    lr: u32,
+    pc: u32,
+    psr: u32,
+}
+
+/// Registers saved by PendSV, followed by the hardware frame.
+#[repr(C)]
+struct StackFrame {
+    /// whether the thread uses the FPU and `s16-s31` follow `r4-r11`
+    #[cfg(target_abi = "eabihf")]
+    flag: u32,
+    r4_r11: [u32; 8],
+    exception_stack_frame: ExceptionStackFrame,
+}
+
+/// With the FPU in use, `s16-s31` saved by PendSV and `s0-s15`, `FPSCR` and a reserved word by the hardware
+#[cfg(target_abi = "eabihf")]
+const FP_FRAME_SIZE: usize = (16 + 18) * size_of::<u32>();
+#[cfg(not(target_abi = "eabihf"))]
+const FP_FRAME_SIZE: usize = 0;
+
+/// Cortex-M0/M3/M4/M7 port.
+pub struct CortexM;
+
+impl Port for CortexM {
+    /// A [`StackFrame`] with the FPU registers, and a word for the 8-byte alignment of the hardware frame.
+    /// Interrupts run on MSP.
+    const STACK_MIN: usize = size_of::<StackFrame>() + FP_FRAME_SIZE + size_of::<u32>();
+
+    #[inline]
+    fn interrupt_disable() -> usize {
+        let level: usize;
+        unsafe {
+            asm!(
+                "MRS {}, PRIMASK",
+                "CPSID I",
+                out(reg) level,
+                options(nostack, preserves_flags),
+            )
+        };
+        level
+    }
+
+    #[inline]
+    fn interrupt_enable(level: usize) {
+        unsafe {
+            asm!(
+                "MSR PRIMASK, {}",
+                in(reg) level,
+                options(nostack, preserves_flags),
+            )
+        };
+    }
+
+    #[inline]
+    fn interrupt_mask(irq: usize) {
+        unsafe { NVIC_ICER.add(irq / 32).write_volatile(1 << (irq % 32)) };
+    }
+
+    #[inline]
+    fn interrupt_unmask(irq: usize) {
+        unsafe { NVIC_ISER.add(irq / 32).write_volatile(1 << (irq % 32)) };
+    }
+
+    unsafe fn stack_init(
+        entry: extern "C" fn(usize),
+        parameter: usize,
+        stack_addr: *mut u8,
+        exit: extern "C" fn() -> !,
+    ) -> *mut u8 {
+        let top = (stack_addr as usize + size_of::<u32>()) & !7;
+        let frame = (top - size_of::<StackFrame>()) as *mut StackFrame;
+        frame.write(StackFrame {
+            #[cfg(target_abi = "eabihf")]
+            flag: 0,
+            r4_r11: [0xdead_beef; 8],
+            exception_stack_frame: ExceptionStackFrame {
+                r0: parameter as _,
+                r1: 0,
+                r2: 0,
+                r3: 0,
+                r12: 0,
+                lr: exit as usize as _,
+                pc: entry as usize as _,
+                psr: XPSR_T,
+            },
+        });
+        frame.cast()
+    }
+
+    #[inline]
+    unsafe fn context_switch(from: *mut NonNull<usize>, to: *mut NonNull<usize>) {
+        Self::context_switch_interrupt(from, to)
+    }
+
+    unsafe fn context_switch_to(to: *mut NonNull<usize>) {
+        INTERRUPT_FROM_THREAD = null_mut();
+        INTERRUPT_TO_THREAD = to;
+        THREAD_SWITCH_INTERRUPT_FLAG = 1;
+
+        SHPR3.write_volatile(SHPR3.read_volatile() | PENDSV_SYSTICK_PRI);
+
+        #[cfg(target_abi = "eabihf")]
+        {
+            // full access to CP10 and CP11
+            CPACR.write_volatile(CPACR.read_volatile() | (0xf << 20));
+            // ASPEN and LSPEN
+            FPCCR.write_volatile(FPCCR.read_volatile() | (0b11 << 30));
+            // no floating-point context is active on the main stack
+            asm!(
+                "MRS {0}, CONTROL",
+                "BIC {0}, {0}, #4",
+                "MSR CONTROL, {0}",
+                "ISB",
+                out(reg) _,
+            );
+        }
+
+        ICSR.write_volatile(PENDSVSET);
+
+        // restore MSP from the vector table, it is only used by exceptions from now on
+        asm!(
+            "LDR {0}, [{0}]",
+            "LDR {0}, [{0}]",
+            "MSR MSP, {0}",
+            "CPSIE I",
+            "B .",
+            in(reg) VTOR,
+            options(noreturn),
+        )
+    }
+
+    #[inline]
+    unsafe fn context_switch_interrupt(from: *mut NonNull<usize>, to: *mut NonNull<usize>) {
+        if THREAD_SWITCH_INTERRUPT_FLAG != 1 {
+            THREAD_SWITCH_INTERRUPT_FLAG = 1;
+            INTERRUPT_FROM_THREAD = from;
+        }
+        INTERRUPT_TO_THREAD = to;
+        ICSR.write_volatile(PENDSVSET);
+    }
+
+    fn tick_init(tick_per_second: usize) {
+        unsafe {
+            SYST_RVR.write_volatile((CPU_FREQUENCY / tick_per_second - 1) as u32);
+            SYST_CVR.write_volatile(0);
+            // processor clock, interrupt, enable
+            SYST_CSR.write_volatile(0b111);
+        }
+        #[cfg(target_feature = "thumb2")]
+        cycle_counter_enable();
+    }
+
+    #[cfg(target_feature = "thumb2")]
+    fn cycle_get() -> u64 {
+        let level = Self::interrupt_disable();
+        cycle_counter_enable();
+        let cycles = unsafe {
+            let now = DWT_CYCCNT.read_volatile();
+            if now < CYCLE_LAST {
+                CYCLE_HIGH += 1;
+            }
+            CYCLE_LAST = now;
+            ((CYCLE_HIGH as u64) << 32) | now as u64
+        };
+        Self::interrupt_enable(level);
+        cycles
+    }
+
+    #[cfg(not(target_feature = "thumb2"))]
+    fn cycle_get() -> u64 {
+        let level = Self::interrupt_disable();
+        let cycles = unsafe {
+            let reload = SYST_RVR.read_volatile() as u64 + 1;
+            let mut tick = crate::clock::tick_get() as u64;
+            let mut current = SYST_CVR.read_volatile();
+            // SysTick wrapped and its interrupt is not taken yet
+            if ICSR.read_volatile() & PENDSTSET != 0 {
+                tick += 1;
+                current = SYST_CVR.read_volatile();
+            }
+            tick * reload + (reload - 1 - current as u64)
+        };
+        Self::interrupt_enable(level);
+        cycles
+    }
+
+    #[inline]
+    fn cycle_frequency() -> u64 {
+        CPU_FREQUENCY as u64
+    }
+}
+
+/// Starts `DWT_CYCCNT` if not running yet, leaving its count as it is.
+#[cfg(target_feature = "thumb2")]
+fn cycle_counter_enable() {
+    unsafe {
+        if DWT_CTRL.read_volatile() & DWT_CTRL_CYCCNTENA == 0 {
+            DEMCR.write_volatile(DEMCR.read_volatile() | DEMCR_TRCENA);
+            DWT_LAR.write_volatile(DWT_LAR_KEY);
+            DWT_CTRL.write_volatile(DWT_CTRL.read_volatile() | DWT_CTRL_CYCCNTENA);
+        }
+    }
+}
+
+/// The SysTick exception handler.
+#[no_mangle]
+extern "C" fn SysTick() {
+    crate::irq::enter();
+    // not to miss a wrap of the cycle counter
+    #[cfg(target_feature = "thumb2")]
+    CortexM::cycle_get();
+    crate::clock::tick_increase();
+    crate::irq::leave();
+}
+
+/// The handler of the exceptions without one of their own in the vector table.
+#[no_mangle]
+extern "C" fn DefaultHandler() {
+    let ipsr: usize;
+    unsafe { asm!("MRS {}, IPSR", out(reg) ipsr, options(nomem, nostack, preserves_flags)) };
+    match ipsr.checked_sub(16) {
+        Some(irq) => crate::irq::dispatch(irq),
+        None => panic!("unhandled exception {ipsr}"),
+    }
+}
+
+/// Called by `HardFault` with the frame pushed by the hardware on the stack in use at the fault.
+extern "C" fn hard_fault(frame: &ExceptionStackFrame, r7: usize, exc_return: usize) -> ! {
+    let sp = frame as *const _ as usize + size_of::<ExceptionStackFrame>();
+    let core = [
+        ("r0", frame.r0 as usize),
+        ("r1", frame.r1 as usize),
+        ("r2", frame.r2 as usize),
+        ("r3", frame.r3 as usize),
+        ("r7", r7),
+        ("r12", frame.r12 as usize),
+        ("lr", frame.lr as usize),
+        ("pc", frame.pc as usize),
+        ("psr", frame.psr as usize),
+        ("sp", sp),
+        ("exc_ret", exc_return),
+    ];
+
+    #[cfg(target_feature = "thumb2")]
+    let registers = {
+        let mut registers = [("", 0); 15];
+        registers[..11].copy_from_slice(&core);
+        registers[11..].copy_from_slice(&unsafe {
+            [
+                ("cfsr", CFSR.read_volatile() as usize),
+                ("hfsr", HFSR.read_volatile() as usize),
+                ("mmfar", MMFAR.read_volatile() as usize),
+                ("bfar", BFAR.read_volatile() as usize),
+            ]
+        });
+        registers
+    };
+    #[cfg(not(target_feature = "thumb2"))]
+    let registers = core;
+
+    fault::handle(&Fault {
+        kind: "hard fault",
+        registers: &registers,
+        frame_pointer: r7,
+    })
+}
+
+global_asm!(
+    ".section .text.HardFault",
+    ".global HardFault",
+    ".type HardFault, %function",
+    ".thumb_func",
+    "HardFault:",
+    // the frame is on the stack told by bit 2 of `EXC_RETURN`
+    "   MOVS    r0, #4",
+    "   MOV     r1, lr",
+    "   TST     r0, r1",
+    "   BEQ     1f",
+    "   MRS     r0, PSP",
+    "   B       2f",
+    "1:",
+    "   MRS     r0, MSP",
+    "2:",
+    "   MOV     r1, r7",
+    "   MOV     r2, lr",
+    "   LDR     r3, ={hard_fault}",
+    "   BX      r3",
+    ".ltorg",
+    ".size HardFault, . - HardFault",
+    hard_fault = sym hard_fault,
+);
+
+#[cfg(target_abi = "eabihf")]
+macro_rules! fpu_save {
+    () => {
+        "   TST     lr, #0x10
+            IT      EQ
+            VSTMDBEQ r1!, {{d8 - d15}}"
+    };
+}
+
+#[cfg(target_abi = "eabihf")]
+macro_rules! fpu_flag {
+    () => {
+        "   MOV     r4, #0
+            TST     lr, #0x10
+            IT      EQ
+            MOVEQ   r4, #1
+            STMFD   r1!, {{r4}}"
+    };
+}
+
+#[cfg(target_abi = "eabihf")]
+macro_rules! fpu_flag_restore {
+    () => {
+        "   LDMFD   r1!, {{r3}}"
+    };
+}
+
+#[cfg(target_abi = "eabihf")]
+macro_rules! fpu_restore {
+    () => {
+        "   CMP     r3, #0
+            IT      NE
+            VLDMIANE r1!, {{d8 - d15}}"
+    };
+}
+
+/// Returns with an extended frame if the thread uses the FPU.
+#[cfg(target_abi = "eabihf")]
+macro_rules! fpu_exc_return {
+    () => {
+        "   ORR     lr, lr, #0x10
+            CMP     r3, #0
+            IT      NE
+            BICNE   lr, lr, #0x10"
+    };
+}
+
+#[cfg(not(target_abi = "eabihf"))]
+macro_rules! fpu_save {
+    () => {
+        ""
+    };
+}
+
+#[cfg(not(target_abi = "eabihf"))]
+use {
+    fpu_save as fpu_flag, fpu_save as fpu_flag_restore, fpu_save as fpu_restore,
+    fpu_save as fpu_exc_return,
+};
+
+#[cfg(target_feature = "thumb2")]
+global_asm!(
+    ".section .text.PendSV",
+    ".global PendSV",
+    ".type PendSV, %function",
+    ".thumb_func",
+    "PendSV:",
+    "   MRS     r2, PRIMASK",
+    "   CPSID   I",
+    // nothing to do if the switch is already done
+    "   LDR     r0, ={flag}",
+    "   LDR     r1, [r0]",
+    "   CBZ     r1, 2f",
+    "   MOVS    r1, #0",
+    "   STR     r1, [r0]",
+    // skip saving when switching to the first thread
+    "   LDR     r0, ={from}",
+    "   LDR     r1, [r0]",
+    "   CBZ     r1, 1f",
+    "   MRS     r1, PSP",
+    fpu_save!(),
+    "   STMFD   r1!, {{r4 - r11}}",
+    fpu_flag!(),
+    "   LDR     r0, [r0]",
+    "   STR     r1, [r0]",
+    "1:",
+    "   LDR     r1, ={to}",
+    "   LDR     r1, [r1]",
+    "   LDR     r1, [r1]",
+    fpu_flag_restore!(),
+    "   LDMFD   r1!, {{r4 - r11}}",
+    fpu_restore!(),
+    "   MSR     PSP, r1",
+    fpu_exc_return!(),
+    "2:",
+    "   MSR     PRIMASK, r2",
+    // return to thread mode on PSP
+    "   ORR     lr, lr, #0x04",
+    "   BX      lr",
+    ".size PendSV, . - PendSV",
+    flag = sym THREAD_SWITCH_INTERRUPT_FLAG,
+    from = sym INTERRUPT_FROM_THREAD,
+    to = sym INTERRUPT_TO_THREAD,
+);
+
+/// ARMv6-M has no `CBZ`, and `STM`/`LDM` only take `r0-r7`.
+#[cfg(not(target_feature = "thumb2"))]
+global_asm!(
+    ".section .text.PendSV",
+    ".global PendSV",
+    ".type PendSV, %function",
+    ".thumb_func",
+    "PendSV:",
+    "   MRS     r2, PRIMASK",
+    "   CPSID   I",
+    "   LDR     r0, ={flag}",
+    "   LDR     r1, [r0]",
+    "   CMP     r1, #0",
+    "   BEQ     2f",
+    "   MOVS    r1, #0",
+    "   STR     r1, [r0]",
+    "   LDR     r0, ={from}",
+    "   LDR     r1, [r0]",
+    "   CMP     r1, #0",
+    "   BEQ     1f",
+    "   MRS     r1, PSP",
+    "   SUBS    r1, r1, #0x20",
+    "   LDR     r0, [r0]",
+    "   STR     r1, [r0]",
+    "   STMIA   r1!, {{r4 - r7}}",
+    "   MOV     r4, r8",
+    "   MOV     r5, r9",
+    "   MOV     r6, r10",
+    "   MOV     r7, r11",
+    "   STMIA   r1!, {{r4 - r7}}",
+    "1:",
+    "   LDR     r1, ={to}",
+    "   LDR     r1, [r1]",
+    "   LDR     r1, [r1]",
+    "   ADDS    r1, r1, #0x10",
+    "   LDMIA   r1!, {{r4 - r7}}",
+    "   MOV     r8, r4",
+    "   MOV     r9, r5",
+    "   MOV     r10, r6",
+    "   MOV     r11, r7",
+    "   MSR     PSP, r1",
+    "   SUBS    r1, r1, #0x20",
+    "   LDMIA   r1!, {{r4 - r7}}",
+    "2:",
+    "   MSR     PRIMASK, r2",
+    // return to thread mode on PSP
+    "   LDR     r0, =0xFFFFFFFD",
+    "   BX      r0",
+    ".size PendSV, . - PendSV",
+    flag = sym THREAD_SWITCH_INTERRUPT_FLAG,
+    from = sym INTERRUPT_FROM_THREAD,
+    to = sym INTERRUPT_TO_THREAD,
+);
@@ -0,0 +1,318 @@
+//! Linux host port, running the kernel as a user-space process.
+//!
+//! Every kernel thread runs on an OS thread of its own, and only the one holding the simulated CPU runs:
+//! a switch hands the CPU over and waits to get it back.
+//! The stack of a kernel thread only holds a frame pointing to its context,
+//! so the stack checks of the kernel still apply.
+//!
+//! Interrupts are raised by any OS thread with [`raise`] and taken by the thread holding the CPU
+//! as soon as it enables interrupts or unmasks one.
+//! Nothing preempts a thread asynchronously: a thread never calling into the kernel keeps the CPU,
+//! and its time slice only runs out at its next kernel call.
+//! The port runs the kernel logic, not its timing on a real CPU.
+//! Interrupts other than [`IRQ_TICK`] go to [`crate::irq::dispatch`], up to `IRQ_MAX`, at most 32.
+//! [`Port::tick_init`] starts an OS thread raising [`IRQ_TICK`] periodically.
+//! Cycles are the nanoseconds of the monotonic clock of `clock_gettime`.
+//!
+//! [`reset`] frees the contexts of a previous run.
+//! Its OS threads stay blocked: they are deep in kernel code, behind `extern "C"` frames that can not be unwound.
+
+use super::Port;
+use crate::{clock, irq, IRQ_MAX};
+use core::{
+    mem::size_of,
+    ptr::{null_mut, NonNull},
+    sync::atomic::{
+        AtomicBool, AtomicU32, AtomicUsize,
+        Ordering::{AcqRel, Acquire, Relaxed},
+    },
+    time::Duration,
+};
+use std::{
+    boxed::Box,
+    sync::{Condvar, Mutex, OnceLock},
+    thread,
+    time::Instant,
+    vec::Vec,
+};
+
+/// Interrupt number of the tick
+pub const IRQ_TICK: usize = 0;
+
+const _: () = assert!(IRQ_MAX <= u32::BITS as usize);
+
+/// Whether interrupts are disabled on the simulated CPU
+static DISABLED: AtomicBool = AtomicBool::new(false);
+/// Raised interrupts not taken yet
+static PENDING: AtomicU32 = AtomicU32::new(0);
+/// Interrupts left pending until unmasked
+static MASKED: AtomicU32 = AtomicU32::new(0);
+/// Bumped by [`reset`] to stop the tick sources of a previous run
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+static NEXT_CONTEXT_ID: AtomicUsize = AtomicUsize::new(1);
+/// Contexts built since the last [`reset`]
+static CONTEXTS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+/// Id of the context holding the CPU
+static CPU: Mutex<usize> = Mutex::new(0);
+static CPU_CHANGED: Condvar = Condvar::new();
+
+static mut INTERRUPT_FROM_THREAD: *mut NonNull<usize> = null_mut();
+static mut INTERRUPT_TO_THREAD: *mut NonNull<usize> = null_mut();
+static mut THREAD_SWITCH_INTERRUPT_FLAG: bool = false;
+
+/// A kernel thread, started on an OS thread the first time it gets the CPU.
+struct Context {
+    id: usize,
+    entry: extern "C" fn(usize),
+    parameter: usize,
+    exit: extern "C" fn() -> !,
+    started: bool,
+}
+
+/// Linux host port.
+pub struct Host;
+
+impl Port for Host {
+    /// A 16-byte frame, aligned to 16 bytes.
+    const STACK_MIN: usize = 32;
+
+    #[inline]
+    fn interrupt_disable() -> usize {
+        DISABLED.swap(true, Relaxed) as usize
+    }
+
+    #[inline]
+    fn interrupt_enable(level: usize) {
+        if level == 0 {
+            DISABLED.store(false, Relaxed);
+            dispatch();
+        }
+    }
+
+    #[inline]
+    fn interrupt_mask(irq: usize) {
+        MASKED.fetch_or(bit(irq), Relaxed);
+    }
+
+    #[inline]
+    fn interrupt_unmask(irq: usize) {
+        MASKED.fetch_and(!bit(irq), Relaxed);
+        dispatch();
+    }
+
+    unsafe fn stack_init(
+        entry: extern "C" fn(usize),
+        parameter: usize,
+        stack_addr: *mut u8,
+        exit: extern "C" fn() -> !,
+    ) -> *mut u8 {
+        let context = Box::new(Context {
+            id: NEXT_CONTEXT_ID.fetch_add(1, Relaxed),
+            entry,
+            parameter,
+            exit,
+            started: false,
+        });
+        let context = Box::into_raw(context) as usize;
+        CONTEXTS.lock().unwrap().push(context);
+        let frame = (((stack_addr as usize + size_of::<usize>()) & !15) - 16) as *mut usize;
+        frame.write(context);
+        frame.cast()
+    }
+
+    unsafe fn context_switch(from: *mut NonNull<usize>, to: *mut NonNull<usize>) {
+        let id = context(from).id;
+        hand_over(context(to));
+        wait_for(id);
+    }
+
+    unsafe fn context_switch_to(to: *mut NonNull<usize>) {
+        hand_over(context(to));
+        loop {
+            thread::park();
+        }
+    }
+
+    unsafe fn context_switch_interrupt(from: *mut NonNull<usize>, to: *mut NonNull<usize>) {
+        if !THREAD_SWITCH_INTERRUPT_FLAG {
+            THREAD_SWITCH_INTERRUPT_FLAG = true;
+            INTERRUPT_FROM_THREAD = from;
+        }
+        INTERRUPT_TO_THREAD = to;
+    }
+
+    fn tick_init(tick_per_second: usize) {
+        let epoch = EPOCH.load(Relaxed);
+        let period = Duration::from_secs(1) / tick_per_second as u32;
+        thread::spawn(move || loop {
+            thread::sleep(period);
+            if EPOCH.load(Relaxed) != epoch {
+                break;
+            }
+            raise(IRQ_TICK);
+        });
+    }
+
+    fn cycle_get() -> u64 {
+        static ORIGIN: OnceLock<Instant> = OnceLock::new();
+        ORIGIN.get_or_init(Instant::now).elapsed().as_nanos() as u64
+    }
+
+    #[inline]
+    fn cycle_frequency() -> u64 {
+        1_000_000_000
+    }
+}
+
+/// Raises interrupt `irq`, from any OS thread.
+pub fn raise(irq: usize) {
+    PENDING.fetch_or(bit(irq), AcqRel);
+}
+
+/// Stops the simulated CPU: the calling kernel thread keeps it and blocks forever.
+pub fn halt() -> ! {
+    DISABLED.store(true, Relaxed);
+    loop {
+        thread::park();
+    }
+}
+
+/// Forgets the CPU and the interrupts of a previous run and frees its contexts,
+/// whose OS threads are left blocked.
+///
+/// The previous run must be halted.
+pub fn reset() {
+    EPOCH.fetch_add(1, Relaxed);
+    PENDING.store(0, Relaxed);
+    MASKED.store(0, Relaxed);
+    DISABLED.store(false, Relaxed);
+    unsafe { THREAD_SWITCH_INTERRUPT_FLAG = false };
+    *CPU.lock().unwrap() = 0;
+    // blocked OS threads only keep the ids of their contexts
+    for context in CONTEXTS.lock().unwrap().drain(..) {
+        drop(unsafe { Box::from_raw(context as *mut Context) });
+    }
+}
+
+/// The bit of `irq` in [`PENDING`] and [`MASKED`].
+fn bit(irq: usize) -> u32 {
+    assert!(irq < IRQ_MAX, "interrupt {irq} out of range");
+    1 << irq
+}
+
+/// Takes the pending interrupts on the thread holding the CPU.
+fn dispatch() {
+    loop {
+        let pending = PENDING.load(Acquire) & !MASKED.load(Relaxed);
+        if pending == 0 || DISABLED.load(Relaxed) {
+            return;
+        }
+        let irq = pending.trailing_zeros() as usize;
+        PENDING.fetch_and(!(1 << irq), AcqRel);
+
+        DISABLED.store(true, Relaxed);
+        if irq == IRQ_TICK {
+            irq::enter();
+            clock::tick_increase();
+            irq::leave();
+        } else {
+            irq::dispatch(irq);
+        }
+        unsafe {
+            if THREAD_SWITCH_INTERRUPT_FLAG {
+                THREAD_SWITCH_INTERRUPT_FLAG = false;
+                Host::context_switch(INTERRUPT_FROM_THREAD, INTERRUPT_TO_THREAD);
+            }
+        }
+        DISABLED.store(false, Relaxed);
+    }
+}
+
+/// The context in the frame built by [`Port::stack_init`].
+unsafe fn context(sp: *mut NonNull<usize>) -> &'static mut Context {
+    &mut *(*(*sp).as_ptr() as *mut Context)
+}
+
+fn hand_over(to: &mut Context) {
+    if !to.started {
+        to.started = true;
+        let &mut Context {
+            id,
+            entry,
+            parameter,
+            exit,
+            ..
+        } = to;
+        thread::spawn(move || {
+            wait_for(id);
+            DISABLED.store(false, Relaxed);
+            dispatch();
+            entry(parameter);
+            exit()
+        });
+    }
+    *CPU.lock().unwrap() = to.id;
+    CPU_CHANGED.notify_all();
+}
+
+fn wait_for(id: usize) {
+    let mut cpu = CPU.lock().unwrap();
+    while *cpu != id {
+        cpu = CPU_CHANGED.wait(cpu).unwrap();
+    }
+}
+
+#[test]
+fn test_boot() {
+    use crate::{cpu, idle, scheduler, sim, thread as rt_thread, thread::Thread};
+    use core::mem::MaybeUninit;
+    use std::sync::mpsc;
+
+    let _lock = crate::test_lock();
+    sim::reset_kernel();
+
+    static B_RUNS: AtomicUsize = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    Thread::spawn_static::<_, ()>(
+        Box::leak(Box::new(MaybeUninit::uninit())),
+        "a",
+        Box::leak(Box::new([0; 256])),
+        1,
+        10,
+        false,
+        move || {
+            rt_thread::sleep(10).unwrap();
+            tx.send((clock::tick_get(), B_RUNS.load(Relaxed))).unwrap();
+            halt()
+        },
+    );
+    Thread::spawn_static::<_, ()>(
+        Box::leak(Box::new(MaybeUninit::uninit())),
+        "b",
+        Box::leak(Box::new([0; 256])),
+        2,
+        10,
+        false,
+        || loop {
+            B_RUNS.fetch_add(1, Relaxed);
+            rt_thread::yield_now();
+        },
+    );
+    idle::init();
+
+    cpu::tick_init();
+    thread::spawn(|| scheduler::start());
+
+    let (tick, b_runs) = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert!(tick >= 10);
+    assert!(b_runs > 0);
+
+    sim::reset_kernel();
+}
+
+#[test]
+#[should_panic(expected = "out of range")]
+fn test_raise_out_of_range() {
+    raise(IRQ_MAX);
+}
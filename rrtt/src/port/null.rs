@@ -0,0 +1,56 @@
+use super::Port;
+use core::ptr::NonNull;
+
+/// Port for targets without one, nothing is switched and interrupts are not touched.
+pub struct Null;
+
+impl Port for Null {
+    const STACK_MIN: usize = 0;
+
+    #[inline]
+    fn interrupt_disable() -> usize {
+        0
+    }
+
+    #[inline]
+    fn interrupt_enable(_level: usize) {}
+
+    #[inline]
+    fn interrupt_mask(_irq: usize) {}
+
+    #[inline]
+    fn interrupt_unmask(_irq: usize) {}
+
+    #[inline]
+    unsafe fn stack_init(
+        _entry: extern "C" fn(usize),
+        _parameter: usize,
+        stack_addr: *mut u8,
+        _exit: extern "C" fn() -> !,
+    ) -> *mut u8 {
+        stack_addr
+    }
+
+    #[inline]
+    unsafe fn context_switch(_from: *mut NonNull<usize>, _to: *mut NonNull<usize>) {}
+
+    #[inline]
+    unsafe fn context_switch_to(_to: *mut NonNull<usize>) {}
+
+    #[inline]
+    unsafe fn context_switch_interrupt(_from: *mut NonNull<usize>, _to: *mut NonNull<usize>) {}
+
+    #[inline]
+    fn tick_init(_tick_per_second: usize) {}
+
+    /// Counts ticks, the only time there is.
+    #[inline]
+    fn cycle_get() -> u64 {
+        crate::clock::tick_get() as u64
+    }
+
+    #[inline]
+    fn cycle_frequency() -> u64 {
+        crate::TICK_PER_SECOND as u64
+    }
+}
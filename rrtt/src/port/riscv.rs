@@ -0,0 +1,378 @@
+//! See [the c code](https://github.com/RT-Thread/rt-thread/tree/v5.1.0/libcpu/risc-v/common).
+//!
+//! The kernel runs in machine mode. Every switch goes through a full trap frame restored by `mret`:
+//! traps save one on the stack of the interrupted thread,
+//! and [`Port::context_switch`] builds one that returns to its caller.
+//! A switch requested in a trap happens when the trap returns.
+//!
+//! The CLINT at env `CLINT_BASE` drives the tick with `mtime` counting at env `TIMEBASE_FREQUENCY`.
+
+use super::Port;
+use crate::{
+    clock,
+    fault::{self, Fault},
+    irq,
+};
+use core::{
+    arch::{asm, global_asm},
+    mem::size_of,
+    ptr::{null_mut, NonNull},
+};
+use konst::{primitive::parse_usize, unwrap_ctx};
+
+const CLINT_BASE: usize = unwrap_ctx!(parse_usize(env!("CLINT_BASE")));
+const TIMEBASE_FREQUENCY: usize = unwrap_ctx!(parse_usize(env!("TIMEBASE_FREQUENCY")));
+const CPU_FREQUENCY: usize = unwrap_ctx!(parse_usize(env!("CPU_FREQUENCY")));
+const CLINT_MTIMECMP: usize = CLINT_BASE + 0x4000;
+const CLINT_MTIME: usize = CLINT_BASE + 0xbff8;
+
+const MSTATUS_MIE: usize = 1 << 3;
+const MSTATUS_MPIE: usize = 1 << 7;
+const MSTATUS_MPP_M: usize = 3 << 11;
+#[cfg(target_feature = "f")]
+const MSTATUS_FS_INITIAL: usize = 1 << 13;
+#[cfg(not(target_feature = "f"))]
+const MSTATUS_FS_INITIAL: usize = 0;
+const MIE_MTIE: usize = 1 << 7;
+
+const MCAUSE_INTERRUPT: usize = 1 << (usize::BITS - 1);
+const IRQ_M_TIMER: usize = 7;
+
+const REGBYTES: usize = size_of::<usize>();
+#[cfg(target_feature = "d")]
+const FREGBYTES: usize = 8;
+#[cfg(all(target_feature = "f", not(target_feature = "d")))]
+const FREGBYTES: usize = 4;
+#[cfg(not(target_feature = "f"))]
+const FREGBYTES: usize = 0;
+
+/// Size of a trap frame: `x1-x31` with `epc` in place of `x0` and `mstatus.MPIE` in place of `sp`,
+/// followed by `f0-f31` if the target has an FPU.
+const FRAME_SIZE: usize = 32 * REGBYTES + 32 * FREGBYTES;
+const FRAME_EPC: usize = 0;
+const FRAME_MSTATUS: usize = 2;
+
+static mut INTERRUPT_FROM_THREAD: *mut NonNull<usize> = null_mut();
+static mut INTERRUPT_TO_THREAD: *mut NonNull<usize> = null_mut();
+static mut THREAD_SWITCH_INTERRUPT_FLAG: usize = 0;
+static mut TICK_CYCLES: u64 = 0;
+
+extern "C" {
+    fn rrtt_trap_entry();
+    fn rrtt_context_switch(from: *mut NonNull<usize>, to: *mut NonNull<usize>);
+    fn rrtt_context_switch_to(to: *mut NonNull<usize>);
+}
+
+/// RISC-V rv32/rv64 port in machine mode.
+pub struct RiscV;
+
+impl Port for RiscV {
+    /// A trap frame, aligned to 16 bytes.
+    /// Traps do not nest, and a switch requested in one is made when it returns.
+    const STACK_MIN: usize = FRAME_SIZE + 16;
+
+    #[inline]
+    fn interrupt_disable() -> usize {
+        let mstatus: usize;
+        unsafe { asm!("csrrci {}, mstatus, {mie}", out(reg) mstatus, mie = const MSTATUS_MIE) };
+        mstatus & MSTATUS_MIE
+    }
+
+    #[inline]
+    fn interrupt_enable(level: usize) {
+        unsafe { asm!("csrs mstatus, {}", in(reg) level & MSTATUS_MIE) };
+    }
+
+    /// `irq` is a bit of `mie`, such as 11 for the external interrupt.
+    #[inline]
+    fn interrupt_mask(irq: usize) {
+        unsafe { asm!("csrc mie, {}", in(reg) 1usize << irq) };
+    }
+
+    #[inline]
+    fn interrupt_unmask(irq: usize) {
+        unsafe { asm!("csrs mie, {}", in(reg) 1usize << irq) };
+    }
+
+    unsafe fn stack_init(
+        entry: extern "C" fn(usize),
+        parameter: usize,
+        stack_addr: *mut u8,
+        exit: extern "C" fn() -> !,
+    ) -> *mut u8 {
+        let top = (stack_addr as usize + size_of::<usize>()) & !15;
+        let frame = (top - FRAME_SIZE) as *mut usize;
+        for i in 0..FRAME_SIZE / REGBYTES {
+            frame.add(i).write(0xdead_beef);
+        }
+
+        let gp: usize;
+        asm!("mv {}, gp", out(reg) gp);
+
+        frame.add(FRAME_EPC).write(entry as usize);
+        frame.add(1).write(exit as usize);
+        frame.add(FRAME_MSTATUS).write(MSTATUS_MPIE);
+        frame.add(3).write(gp);
+        frame.add(4).write(0);
+        frame.add(10).write(parameter);
+        frame.cast()
+    }
+
+    #[inline]
+    unsafe fn context_switch(from: *mut NonNull<usize>, to: *mut NonNull<usize>) {
+        rrtt_context_switch(from, to)
+    }
+
+    #[inline]
+    unsafe fn context_switch_to(to: *mut NonNull<usize>) {
+        rrtt_context_switch_to(to)
+    }
+
+    #[inline]
+    unsafe fn context_switch_interrupt(from: *mut NonNull<usize>, to: *mut NonNull<usize>) {
+        if THREAD_SWITCH_INTERRUPT_FLAG != 1 {
+            THREAD_SWITCH_INTERRUPT_FLAG = 1;
+            INTERRUPT_FROM_THREAD = from;
+        }
+        INTERRUPT_TO_THREAD = to;
+    }
+
+    fn tick_init(tick_per_second: usize) {
+        unsafe {
+            TICK_CYCLES = (TIMEBASE_FREQUENCY / tick_per_second) as u64;
+            set_mtimecmp(mtime() + TICK_CYCLES);
+            asm!("csrs mie, {}", in(reg) MIE_MTIE);
+        }
+    }
+
+    /// `mcycle`, at `CPU_FREQUENCY`.
+    fn cycle_get() -> u64 {
+        #[cfg(target_pointer_width = "64")]
+        {
+            let cycle: u64;
+            unsafe { asm!("csrr {}, mcycle", out(reg) cycle) };
+            cycle
+        }
+
+        #[cfg(target_pointer_width = "32")]
+        loop {
+            let (hi, lo, hi_again): (u32, u32, u32);
+            unsafe {
+                asm!(
+                    "csrr {}, mcycleh",
+                    "csrr {}, mcycle",
+                    "csrr {}, mcycleh",
+                    out(reg) hi,
+                    out(reg) lo,
+                    out(reg) hi_again,
+                )
+            };
+            if hi == hi_again {
+                break ((hi as u64) << 32) | lo as u64;
+            }
+        }
+    }
+
+    #[inline]
+    fn cycle_frequency() -> u64 {
+        CPU_FREQUENCY as u64
+    }
+}
+
+/// Called by the trap entry with the trap frame on the stack of the interrupted thread.
+extern "C" fn trap_handler(mcause: usize, frame: *mut usize) {
+    match mcause {
+        c if c == MCAUSE_INTERRUPT | IRQ_M_TIMER => {
+            irq::enter();
+            set_mtimecmp(mtime() + unsafe { TICK_CYCLES });
+            clock::tick_increase();
+            irq::leave();
+        }
+        c if c & MCAUSE_INTERRUPT != 0 => irq::dispatch(c & !MCAUSE_INTERRUPT),
+        _ => fault(mcause, frame),
+    }
+}
+
+/// Reports an exception from the trap frame.
+fn fault(mcause: usize, frame: *mut usize) -> ! {
+    const NAMES: [&str; 32] = [
+        "mepc", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+        "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+        "t5", "t6",
+    ];
+
+    let mtval: usize;
+    unsafe { asm!("csrr {}, mtval", out(reg) mtval) };
+    let mut registers = [("mcause", mcause); 34];
+    registers[1] = ("mtval", mtval);
+    for (i, name) in NAMES.into_iter().enumerate() {
+        registers[2 + i] = (name, unsafe { *frame.add(i) });
+    }
+    // the slot of `sp` holds `mstatus.MPIE`
+    registers[2 + 2].1 = frame as usize + FRAME_SIZE;
+
+    fault::handle(&Fault {
+        kind: "unhandled exception",
+        registers: &registers,
+        frame_pointer: registers[2 + 8].1,
+    })
+}
+
+fn hart_id() -> usize {
+    let id: usize;
+    unsafe { asm!("csrr {}, mhartid", out(reg) id) };
+    id
+}
+
+fn mtime() -> u64 {
+    #[cfg(target_pointer_width = "64")]
+    unsafe {
+        (CLINT_MTIME as *const u64).read_volatile()
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    loop {
+        let mtime = CLINT_MTIME as *const u32;
+        let hi = unsafe { mtime.add(1).read_volatile() };
+        let lo = unsafe { mtime.read_volatile() };
+        if hi == unsafe { mtime.add(1).read_volatile() } {
+            break ((hi as u64) << 32) | lo as u64;
+        }
+    }
+}
+
+fn set_mtimecmp(time: u64) {
+    let mtimecmp = CLINT_MTIMECMP + 8 * hart_id();
+
+    #[cfg(target_pointer_width = "64")]
+    unsafe {
+        (mtimecmp as *mut u64).write_volatile(time)
+    };
+
+    // never lower than both the old and the new value while half written
+    #[cfg(target_pointer_width = "32")]
+    unsafe {
+        let mtimecmp = mtimecmp as *mut u32;
+        mtimecmp.write_volatile(u32::MAX);
+        mtimecmp.add(1).write_volatile((time >> 32) as u32);
+        mtimecmp.write_volatile(time as u32);
+    }
+}
+
+global_asm!(
+    ".macro rrtt_load reg, base, off",
+    ".if {regbytes} == 8",
+    "   ld  \\reg, \\off * 8(\\base)",
+    ".else",
+    "   lw  \\reg, \\off * 4(\\base)",
+    ".endif",
+    ".endm",
+    ".macro rrtt_store reg, base, off",
+    ".if {regbytes} == 8",
+    "   sd  \\reg, \\off * 8(\\base)",
+    ".else",
+    "   sw  \\reg, \\off * 4(\\base)",
+    ".endif",
+    ".endm",
+    ".macro rrtt_fload reg, off",
+    ".if {fregbytes} == 8",
+    "   fld \\reg, 32 * {regbytes} + \\off * 8(sp)",
+    ".elseif {fregbytes} == 4",
+    "   flw \\reg, 32 * {regbytes} + \\off * 4(sp)",
+    ".endif",
+    ".endm",
+    ".macro rrtt_fstore reg, off",
+    ".if {fregbytes} == 8",
+    "   fsd \\reg, 32 * {regbytes} + \\off * 8(sp)",
+    ".elseif {fregbytes} == 4",
+    "   fsw \\reg, 32 * {regbytes} + \\off * 4(sp)",
+    ".endif",
+    ".endm",
+    // saves x1 and x3-x31, and the FPU registers, into a new frame
+    ".macro rrtt_save_frame",
+    "   addi    sp, sp, -{frame_size}",
+    "   rrtt_store x1, sp, 1",
+    ".irp i, 3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+    "   rrtt_store x\\i, sp, \\i",
+    ".endr",
+    ".irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+    "   rrtt_fstore f\\i, \\i",
+    ".endr",
+    ".endm",
+    //
+    ".section .text.rrtt_trap_entry",
+    ".global rrtt_trap_entry",
+    ".align 2",
+    "rrtt_trap_entry:",
+    "   rrtt_save_frame",
+    "   csrr    t0, mepc",
+    "   rrtt_store t0, sp, {epc}",
+    "   csrr    t0, mstatus",
+    "   andi    t0, t0, {mpie}",
+    "   rrtt_store t0, sp, {mstatus}",
+    "   csrr    a0, mcause",
+    "   mv      a1, sp",
+    "   call    {trap_handler}",
+    // switch if requested by the trap handler
+    "   la      t0, {flag}",
+    "   rrtt_load t1, t0, 0",
+    "   beqz    t1, rrtt_restore_frame",
+    "   rrtt_store zero, t0, 0",
+    "   la      t0, {from}",
+    "   rrtt_load t0, t0, 0",
+    "   rrtt_store sp, t0, 0",
+    "   la      t0, {to}",
+    "   rrtt_load t0, t0, 0",
+    "   rrtt_load sp, t0, 0",
+    "   j       rrtt_restore_frame",
+    //
+    ".section .text.rrtt_context_switch",
+    ".global rrtt_context_switch",
+    "rrtt_context_switch:",
+    "   rrtt_save_frame",
+    // return to the caller, with the interrupt state of the caller
+    "   rrtt_store ra, sp, {epc}",
+    "   csrr    t0, mstatus",
+    "   andi    t0, t0, {mie}",
+    "   slli    t0, t0, 4",
+    "   rrtt_store t0, sp, {mstatus}",
+    "   rrtt_store sp, a0, 0",
+    "   rrtt_load sp, a1, 0",
+    "   j       rrtt_restore_frame",
+    //
+    ".section .text.rrtt_context_switch_to",
+    ".global rrtt_context_switch_to",
+    "rrtt_context_switch_to:",
+    "   la      t0, rrtt_trap_entry",
+    "   csrw    mtvec, t0",
+    "   rrtt_load sp, a0, 0",
+    //
+    "rrtt_restore_frame:",
+    "   li      t0, {mstatus_base}",
+    "   csrw    mstatus, t0",
+    "   rrtt_load t0, sp, {mstatus}",
+    "   csrs    mstatus, t0",
+    "   rrtt_load t0, sp, {epc}",
+    "   csrw    mepc, t0",
+    ".irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+    "   rrtt_fload f\\i, \\i",
+    ".endr",
+    "   rrtt_load x1, sp, 1",
+    ".irp i, 3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+    "   rrtt_load x\\i, sp, \\i",
+    ".endr",
+    "   addi    sp, sp, {frame_size}",
+    "   mret",
+    regbytes = const REGBYTES,
+    fregbytes = const FREGBYTES,
+    frame_size = const FRAME_SIZE,
+    epc = const FRAME_EPC,
+    mstatus = const FRAME_MSTATUS,
+    mie = const MSTATUS_MIE,
+    mpie = const MSTATUS_MPIE,
+    mstatus_base = const MSTATUS_MPP_M | MSTATUS_FS_INITIAL,
+    trap_handler = sym trap_handler,
+    flag = sym THREAD_SWITCH_INTERRUPT_FLAG,
+    from = sym INTERRUPT_FROM_THREAD,
+    to = sym INTERRUPT_TO_THREAD,
+);
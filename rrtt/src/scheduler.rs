@@ -1,24 +1,31 @@
-﻿use crate::{list, thread::Thread, PRIORITY_MAX};
+﻿use crate::{
+    cpu::{self, InterruptFreeCell},
+    irq, list,
+    thread::{Thread, ThreadState, STACK_MAGIC},
+    PRIORITY_MAX,
+};
 use core::{mem::MaybeUninit, ptr::null_mut};
 
-static mut LOCK_NEST: u16 = 0;
-static mut CURRENT_PRIORITY: u8 = 0;
-static mut READY_PRIORITY_GROUP: u32 = 0;
-static mut PRIORITY_TABLE: [MaybeUninit<list::Node>; PRIORITY_MAX] =
-    unsafe { MaybeUninit::uninit().assume_init() };
-static mut THREAD_DEFUNCT: MaybeUninit<list::Node> = MaybeUninit::uninit();
+static LOCK_NEST: InterruptFreeCell<u16> = InterruptFreeCell::new(0);
+static CURRENT_PRIORITY: InterruptFreeCell<u8> = InterruptFreeCell::new(0);
+static READY_PRIORITY_GROUP: InterruptFreeCell<u32> = InterruptFreeCell::new(0);
+static PRIORITY_TABLE: InterruptFreeCell<[MaybeUninit<list::Node>; PRIORITY_MAX]> =
+    InterruptFreeCell::new(unsafe { MaybeUninit::uninit().assume_init() });
+pub(crate) static THREAD_DEFUNCT: InterruptFreeCell<MaybeUninit<list::Node>> =
+    InterruptFreeCell::new(MaybeUninit::uninit());
 
 #[cfg(large_priority)]
-static mut READY_TABLE: [u8; 32] = [0; 32];
+static READY_TABLE: InterruptFreeCell<[u8; 32]> = InterruptFreeCell::new([0; 32]);
 
-pub(crate) static mut CURRENT_THREAD: *mut Thread = null_mut();
+pub(crate) static CURRENT_THREAD: InterruptFreeCell<*mut Thread> =
+    InterruptFreeCell::new(null_mut());
 
 pub struct LockNestedGuard;
 
 impl LockNestedGuard {
     #[inline]
     pub fn new() -> Self {
-        unsafe { LOCK_NEST += 1 };
+        LOCK_NEST.with(|nest| *nest += 1);
         Self
     }
 }
@@ -26,54 +33,172 @@ impl LockNestedGuard {
 impl Drop for LockNestedGuard {
     #[inline]
     fn drop(&mut self) {
-        unsafe { LOCK_NEST -= 1 };
+        LOCK_NEST.with(|nest| *nest -= 1);
     }
 }
 
+/// The running thread, null before the scheduler starts.
+///
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+#[inline]
+pub(crate) fn current_thread() -> *mut Thread {
+    CURRENT_THREAD.with(|thread| *thread)
+}
+
+/// The running thread like [`current_thread`], or `None` if called while it is lent.
+#[inline]
+pub(crate) fn try_current_thread() -> Option<*mut Thread> {
+    CURRENT_THREAD.try_with(|thread| *thread)
+}
+
 /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/scheduler.c#L123).
 pub fn init() {
-    unsafe {
-        LOCK_NEST = 0;
-        CURRENT_PRIORITY = 0;
-        CURRENT_THREAD = null_mut();
-        READY_PRIORITY_GROUP = 0;
-        for node in &mut PRIORITY_TABLE {
-            node.assume_init().init();
+    LOCK_NEST.with(|nest| *nest = 0);
+    CURRENT_PRIORITY.with(|priority| *priority = 0);
+    CURRENT_THREAD.with(|thread| *thread = null_mut());
+    READY_PRIORITY_GROUP.with(|group| *group = 0);
+    PRIORITY_TABLE.with(|table| {
+        for node in table {
+            node.write(unsafe { list::Node::new_empty(node.as_ptr()) });
         }
+    });
 
-        #[cfg(large_priority)]
-        {
-            READY_TABLE.fill(0)
-        };
+    #[cfg(large_priority)]
+    READY_TABLE.with(|table| table.fill(0));
 
-        THREAD_DEFUNCT.assume_init().init();
-    }
+    THREAD_DEFUNCT.with(|defunct| {
+        defunct.write(unsafe { list::Node::new_empty(defunct.as_ptr()) });
+    });
 }
 
 /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/scheduler.c#L152).
 pub fn start() -> ! {
+    let to_thread = unsafe { &mut *highest_ready_thread() };
+    CURRENT_THREAD.with(|thread| *thread = to_thread);
+
+    #[cfg(feature = "overflow-check")]
+    stack_check(to_thread);
+
+    unsafe { cpu::context_switch_to(&mut to_thread.sp) };
+
+    unreachable!()
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/scheduler.c).
+pub fn schedule() {
+    let guard = cpu::InterruptFreeGuard::new();
+    if LOCK_NEST.with(|nest| *nest) != 0 {
+        return;
+    }
+
+    let to_thread = highest_ready_thread();
+    let from_thread = current_thread();
+    if to_thread != from_thread {
+        CURRENT_PRIORITY.with(|priority| *priority = unsafe { (*to_thread).current_priority });
+        CURRENT_THREAD.with(|thread| *thread = to_thread);
+        // TODO HOOK
+
+        #[cfg(feature = "overflow-check")]
+        stack_check(unsafe { &*to_thread });
+
+        if irq::in_interrupt() {
+            unsafe { cpu::context_switch_interrupt(&mut (*from_thread).sp, &mut (*to_thread).sp) };
+        } else {
+            unsafe { cpu::context_switch(&mut (*from_thread).sp, &mut (*to_thread).sp) };
+            drop(guard);
+
+            #[cfg(feature = "signals")]
+            crate::signal::handle_pending();
+        }
+    }
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/scheduler.c).
+#[cfg(feature = "overflow-check")]
+fn stack_check(thread: &Thread) {
+    let sp = thread.sp.as_ptr() as usize;
+    let bottom = thread.stack_address.as_ptr() as usize;
+    if unsafe { *thread.stack_address.as_ptr().cast::<u8>() } != STACK_MAGIC
+        || sp <= bottom
+        || sp > bottom + thread.stack_size
+    {
+        let _guard = cpu::InterruptFreeGuard::new();
+        panic!("thread {} stack overflow", thread.header.name());
+    }
+}
+
+/// Finds the first thread in the highest non-empty ready list.
+fn highest_ready_thread() -> *mut Thread {
     let highest_ready_priority = {
         #[cfg(large_priority)]
         {
-            let number = unsafe { READY_PRIORITY_GROUP }.trailing_zeros() as usize;
-            (number << 3) + unsafe { READY_TABLE[number] }.trailing_zeros();
+            let number = READY_PRIORITY_GROUP.with(|group| group.trailing_zeros()) as usize;
+            (number << 3) + READY_TABLE.with(|table| table[number].trailing_zeros()) as usize
         }
 
         #[cfg(small_priority)]
         {
-            unsafe { READY_PRIORITY_GROUP }.trailing_zeros() as usize
+            READY_PRIORITY_GROUP.with(|group| group.trailing_zeros()) as usize
         }
     };
 
-    let to_thread = container_of!(
-        unsafe { PRIORITY_TABLE[highest_ready_priority].next },
-        Thread,
-        list
-    );
-    unsafe { CURRENT_THREAD = to_thread.cast_mut() };
+    let next = PRIORITY_TABLE
+        .with(|table| unsafe { table[highest_ready_priority].assume_init_ref() }.next);
+    container_of!(next.as_ptr(), Thread, list).cast_mut()
+}
 
-    //     /* switch to new thread */
-    //     rt_hw_context_switch_to((rt_uint32_t)&to_thread->sp);
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/scheduler.c).
+pub(crate) fn insert_thread(thread: &mut Thread) {
+    let _guard = cpu::InterruptFreeGuard::new();
+    thread.stat = ThreadState::Ready;
+    let priority = thread.current_priority as usize;
+    PRIORITY_TABLE
+        .with(|table| unsafe { table[priority].assume_init_mut() }.insert_before(&mut thread.list));
 
-    unreachable!()
+    #[cfg(large_priority)]
+    READY_TABLE.with(|table| table[thread.number as usize] |= thread.high_mask);
+
+    READY_PRIORITY_GROUP.with(|group| *group |= thread.number_mask as u32);
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/scheduler.c).
+pub(crate) fn remove_thread(thread: &mut Thread) {
+    let _guard = cpu::InterruptFreeGuard::new();
+    thread.list.remove();
+    let priority = thread.current_priority as usize;
+    if PRIORITY_TABLE.with(|table| unsafe { table[priority].assume_init_ref() }.is_empty()) {
+        #[cfg(large_priority)]
+        {
+            let empty = READY_TABLE.with(|table| {
+                table[thread.number as usize] &= !thread.high_mask;
+                table[thread.number as usize] == 0
+            });
+            if empty {
+                READY_PRIORITY_GROUP.with(|group| *group &= !(thread.number_mask as u32));
+            }
+        }
+
+        #[cfg(small_priority)]
+        READY_PRIORITY_GROUP.with(|group| *group &= !(thread.number_mask as u32));
+    }
+}
+
+#[cfg(feature = "overflow-check")]
+#[test]
+#[should_panic(expected = "thread ovf stack overflow")]
+fn test_stack_check() {
+    extern "C" fn entry(_: usize) {}
+
+    let _lock = crate::test_lock();
+    crate::object::reset();
+    let thread = std::boxed::Box::leak(std::boxed::Box::new(MaybeUninit::uninit()));
+    let stack = std::boxed::Box::leak(std::boxed::Box::new([0usize; 64]));
+    let base = stack.as_mut_ptr().cast::<u8>();
+    Thread::init(thread, "ovf", entry, 0, stack, 1, 10);
+    let thread = unsafe { thread.assume_init_mut() };
+    stack_check(thread);
+
+    // the thread wrote past the bottom of its stack
+    unsafe { *base = 0 };
+    stack_check(thread);
 }
@@ -0,0 +1,355 @@
+use crate::{
+    cpu::{self, InterruptFreeGuard},
+    error::Error,
+    irq,
+    scheduler::{self, current_thread},
+    thread::{self, SuspendFlag, Thread, ThreadState},
+};
+use core::{mem::size_of, ptr::NonNull};
+
+/// Number of signals of a thread
+pub const SIG_MAX: usize = 32;
+
+/// The kill signal, which can not be masked
+///
+/// A thread without a handler for it exits with `SIG_KILL` as its exit code.
+pub const SIG_KILL: usize = 9;
+
+/// The thread has signals to handle
+pub(crate) const STAT_SIGNAL: u8 = 0x1;
+/// The thread is resumed to handle signals
+pub(crate) const STAT_SIGNAL_PENDING: u8 = 0x2;
+/// The thread is waiting for signals
+pub(crate) const STAT_SIGNAL_WAIT: u8 = 0x4;
+
+/// Stack space skipped below the saved context when a signal frame is pushed.
+const SIG_FRAME_GAP: usize = size_of::<[usize; 8]>();
+
+#[inline]
+const fn sig_mask(signo: usize) -> u32 {
+    1 << signo
+}
+
+/// Signals of the thread that are pending and not blocked.
+#[inline]
+fn deliverable(thread: &Thread) -> u32 {
+    thread.sig_pending & (thread.sig_mask | sig_mask(SIG_KILL))
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/signal.c).
+///
+/// `None` ignores the signal, except for [`SIG_KILL`] which then exits the thread.
+/// Returns the previous handler.
+pub fn install(signo: usize, handler: Option<fn(usize)>) -> Result<Option<fn(usize)>, Error> {
+    if signo >= SIG_MAX {
+        return Err(Error::Inval);
+    }
+
+    let thread = unsafe { &mut *current_thread() };
+    let _guard = InterruptFreeGuard::new();
+    Ok(core::mem::replace(&mut thread.sig_vectors[signo], handler))
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/signal.c).
+pub fn mask(signo: usize) {
+    assert!(signo < SIG_MAX);
+
+    let thread = unsafe { &mut *current_thread() };
+    let _guard = InterruptFreeGuard::new();
+    thread.sig_mask &= !sig_mask(signo);
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/signal.c).
+pub fn unmask(signo: usize) {
+    assert!(signo < SIG_MAX);
+
+    let thread = unsafe { &mut *current_thread() };
+    {
+        let _guard = InterruptFreeGuard::new();
+        thread.sig_mask |= sig_mask(signo);
+        if thread.sig_mask & thread.sig_pending == 0 {
+            return;
+        }
+    }
+    deliver(thread);
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/signal.c).
+///
+/// Signals sent again before being handled are merged.
+/// Sent from an interrupt to the interrupted thread, they are handled once it is switched back in.
+pub fn kill(thread: &mut Thread, signo: usize) -> Result<(), Error> {
+    if signo >= SIG_MAX {
+        return Err(Error::Inval);
+    }
+
+    {
+        let _guard = InterruptFreeGuard::new();
+        thread.sig_pending |= sig_mask(signo);
+    }
+    deliver(thread);
+    Ok(())
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/signal.c).
+fn deliver(thread: &mut Thread) {
+    let guard = InterruptFreeGuard::new();
+    let pending = deliverable(thread);
+    if pending == 0 {
+        return;
+    }
+
+    if let ThreadState::Suspend(flag) = thread.stat {
+        // handled once the thread wakes up, by a signal or otherwise
+        thread.sig_stat |= STAT_SIGNAL | STAT_SIGNAL_PENDING;
+        let wake = match flag {
+            SuspendFlag::Interruptible => true,
+            SuspendFlag::Killable => pending & sig_mask(SIG_KILL) != 0,
+            SuspendFlag::Uninterruptible => false,
+        };
+        if wake {
+            // the wait fails with EINTR, whatever it set beforehand
+            thread.error = Err(Error::Intr);
+            // also takes the thread off the list it waits on
+            let _ = thread.resume();
+            drop(guard);
+
+            scheduler::schedule();
+        }
+    } else if core::ptr::eq(current_thread(), thread) {
+        if irq::in_interrupt() {
+            // handled once the interrupted thread is switched back in
+            thread.sig_stat |= STAT_SIGNAL | STAT_SIGNAL_PENDING;
+            return;
+        }
+        thread.sig_stat |= STAT_SIGNAL;
+        drop(guard);
+
+        handle_sig(true);
+    } else if thread.sig_stat & STAT_SIGNAL == 0 {
+        thread.sig_stat |= STAT_SIGNAL;
+        // the thread will run the signal entry on its own stack once switched to
+        thread.sig_ret = thread.sp;
+        let frame = unsafe { thread.sp.as_ptr().cast::<u8>().sub(SIG_FRAME_GAP) };
+        let sp = unsafe { cpu::stack_init(signal_entry, 0, frame, thread::thread_exit) };
+        thread.sp = unsafe { NonNull::new_unchecked(sp.cast()) };
+    }
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/signal.c).
+extern "C" fn signal_entry(_parameter: usize) {
+    let thread = unsafe { &mut *current_thread() };
+    handle_sig(false);
+
+    let _guard = InterruptFreeGuard::new();
+    thread.sp = thread.sig_ret;
+    thread.sig_stat &= !STAT_SIGNAL;
+    unsafe { cpu::context_switch_to(&mut thread.sp) };
+}
+
+/// Handles signals of the current thread if it is resumed for them.
+///
+/// Called after the current thread is switched back in.
+pub(crate) fn handle_pending() {
+    let thread = unsafe { &mut *current_thread() };
+    {
+        let _guard = InterruptFreeGuard::new();
+        if thread.sig_stat & STAT_SIGNAL_PENDING == 0 {
+            return;
+        }
+        thread.sig_stat &= !STAT_SIGNAL_PENDING;
+    }
+    handle_sig(true);
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/signal.c).
+pub(crate) fn handle_sig(clean_state: bool) {
+    let thread = unsafe { &mut *current_thread() };
+    let mut guard = InterruptFreeGuard::new();
+    if thread.sig_stat & STAT_SIGNAL_WAIT != 0 {
+        return;
+    }
+
+    let error = thread.error;
+    loop {
+        let pending = deliverable(thread);
+        if pending == 0 {
+            break;
+        }
+        let signo = pending.trailing_zeros() as usize;
+        thread.sig_pending &= !sig_mask(signo);
+        let handler = thread.sig_vectors[signo];
+        drop(guard);
+
+        match handler {
+            Some(handler) => handler(signo),
+            None if signo == SIG_KILL => thread::exit_with(SIG_KILL),
+            None => {}
+        }
+
+        guard = InterruptFreeGuard::new();
+        // handlers may block, keep the result of what the thread was waiting for
+        thread.error = error;
+    }
+
+    if clean_state {
+        thread.sig_stat &= !STAT_SIGNAL;
+    }
+}
+
+#[test]
+fn test_signal() {
+    use crate::sim::{log, Sim};
+    use core::mem::MaybeUninit;
+    use std::boxed::Box;
+
+    fn handler(signo: usize) {
+        log(if signo == 3 { "handles 3" } else { "handles 4" });
+    }
+
+    let mut sim = Sim::new(0);
+    sim.spawn("a", 2, || {
+        if install(SIG_MAX, Some(handler)).err() == Some(Error::Inval)
+            && install(3, Some(handler)).is_ok_and(|old| old.is_none())
+        {
+            log("installed");
+        }
+        install(4, Some(handler)).unwrap();
+        unmask(3);
+
+        // delivered to a ready thread when it is switched back to
+        let a = current_thread() as usize;
+        Thread::spawn_static(
+            Box::leak(Box::new(MaybeUninit::uninit())),
+            "b",
+            Box::leak(Box::new([0; 256])),
+            1,
+            10,
+            false,
+            move || {
+                kill(unsafe { &mut *(a as *mut Thread) }, 3).unwrap();
+                log("b kills a");
+            },
+        );
+        log("a continues");
+
+        // held until unmasked
+        kill(unsafe { &mut *current_thread() }, 4).unwrap();
+        log("4 blocked");
+        unmask(4);
+        log("a done");
+    });
+    assert_eq!(
+        [
+            (0, "installed"),
+            (0, "b kills a"),
+            (0, "handles 3"),
+            (0, "a continues"),
+            (0, "4 blocked"),
+            (0, "handles 4"),
+            (0, "a done"),
+        ],
+        &*sim.run(1),
+    );
+}
+
+#[test]
+fn test_kill_in_interrupt() {
+    use crate::sim::{log, Sim};
+
+    fn handler(_: usize) {
+        log("handles 3");
+    }
+
+    let mut sim = Sim::new(0);
+    sim.spawn("a", 2, || {
+        install(3, Some(handler)).unwrap();
+        unmask(3);
+
+        // as if an interrupt preempted the thread
+        irq::enter();
+        kill(unsafe { &mut *current_thread() }, 3).unwrap();
+        log("interrupt left");
+        irq::leave();
+
+        thread::sleep(1).unwrap();
+        log("a done");
+    });
+    assert_eq!(
+        [(0, "interrupt left"), (1, "handles 3"), (1, "a done")],
+        &*sim.run(2),
+    );
+}
+
+#[test]
+fn test_interrupt_wait() {
+    use crate::sim::{log, Sim};
+    use core::mem::MaybeUninit;
+    use std::boxed::Box;
+
+    fn handler(_signo: usize) {
+        log("handles 3");
+    }
+
+    let mut sim = Sim::new(0);
+    let a = sim.spawn("a", 1, || {
+        install(3, Some(handler)).unwrap();
+        unmask(3);
+        if thread::sleep(10) == Err(Error::Intr) {
+            log("sleep interrupted");
+        }
+    }) as *mut Thread as usize;
+    let c = Thread::spawn_static(
+        Box::leak(Box::new(MaybeUninit::uninit())),
+        "c",
+        Box::leak(Box::new([0; 256])),
+        2,
+        10,
+        true,
+        || {
+            install(3, Some(handler)).unwrap();
+            unmask(3);
+            let c = unsafe { &mut *current_thread() };
+            c.suspend_with_flag(SuspendFlag::Killable).unwrap();
+            scheduler::schedule();
+            log("c woken");
+        },
+    ) as *mut Thread as usize;
+    // a wait that does not set the error beforehand
+    let d = sim.spawn("d", 2, || {
+        unmask(3);
+        let d = unsafe { &mut *current_thread() };
+        d.error = Ok(());
+        d.suspend_with_flag(SuspendFlag::Interruptible).unwrap();
+        scheduler::schedule();
+        if d.error == Err(Error::Intr) {
+            log("d interrupted");
+        }
+    }) as *mut Thread as usize;
+    sim.spawn("b", 3, move || {
+        let (a, c) = unsafe { (&mut *(a as *mut Thread), &mut *(c as *mut Thread)) };
+        kill(a, 3).unwrap();
+        log("b kills a");
+        kill(unsafe { &mut *(d as *mut Thread) }, 3).unwrap();
+        kill(c, 3).unwrap();
+        if c.stat() == ThreadState::Suspend(SuspendFlag::Killable) {
+            log("c still suspended");
+        }
+        kill(c, SIG_KILL).unwrap();
+        if c.join() == Ok(SIG_KILL) {
+            log("c killed");
+        }
+    });
+    assert_eq!(
+        [
+            (0, "handles 3"),
+            (0, "sleep interrupted"),
+            (0, "b kills a"),
+            (0, "d interrupted"),
+            (0, "c still suspended"),
+            (0, "handles 3"),
+            (0, "c killed"),
+        ],
+        &*sim.run(1),
+    );
+}
@@ -0,0 +1,224 @@
+//! Deterministic simulation on the host port, in virtual time.
+//!
+//! No OS timer drives the tick: the idle hook raises the next tick whenever all threads are blocked,
+//! so a run only depends on the threads, the scripted interrupts and the seed.
+//! A thread that never blocks stops the virtual time.
+
+use crate::{
+    clock, idle, irq, object,
+    port::host::{self, IRQ_TICK},
+    scheduler,
+    thread::{self as rt_thread, Thread},
+    timer,
+};
+use core::{mem::MaybeUninit, time::Duration};
+use std::{
+    boxed::Box,
+    collections::BTreeMap,
+    sync::{mpsc, Mutex, MutexGuard},
+    thread,
+    vec::Vec,
+};
+
+/// Interrupt number of the scripted interrupts
+const IRQ_SIM: usize = 1;
+
+type Action = Box<dyn FnMut() + Send>;
+
+struct State {
+    /// interrupts to take at each tick
+    schedule: BTreeMap<usize, Vec<Action>>,
+    until: usize,
+    log: Vec<(usize, &'static str)>,
+    done: Option<mpsc::Sender<()>>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    schedule: BTreeMap::new(),
+    until: 0,
+    log: Vec::new(),
+    done: None,
+});
+
+/// A simulated system, booted by [`Sim::run`].
+pub(crate) struct Sim {
+    rng: u64,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Sim {
+    /// Resets the kernel; threads are then created with [`Sim::spawn`].
+    pub fn new(seed: u64) -> Self {
+        let lock = crate::test_lock();
+        reset_kernel();
+        let _ = idle::del_hook(on_idle);
+        let mut state = state();
+        state.schedule.clear();
+        state.log.clear();
+        Self {
+            // xorshift gets stuck at 0
+            rng: seed ^ 0x9e37_79b9_7f4a_7c15,
+            _lock: lock,
+        }
+    }
+
+    /// Creates and starts a thread running `f`.
+    pub fn spawn(
+        &mut self,
+        name: &str,
+        priority: u8,
+        f: impl FnOnce() + Send + 'static,
+    ) -> &'static mut Thread {
+        Thread::spawn_static(
+            Box::leak(Box::new(MaybeUninit::uninit())),
+            name,
+            Box::leak(Box::new([0; 256])),
+            priority,
+            10,
+            false,
+            f,
+        )
+    }
+
+    /// Calls `isr` in interrupt context right after the tick reaches `tick`.
+    pub fn at(&mut self, tick: usize, isr: impl FnMut() + Send + 'static) {
+        state()
+            .schedule
+            .entry(tick)
+            .or_default()
+            .push(Box::new(isr));
+    }
+
+    /// A pseudo-random number from the seed.
+    pub fn random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    /// Boots the kernel and runs it until tick `until`, returning the events logged by [`log`].
+    ///
+    /// Panics if the virtual time gets stuck.
+    pub fn run(self, until: usize) -> Vec<(usize, &'static str)> {
+        let (tx, rx) = mpsc::channel();
+        {
+            let mut state = state();
+            state.until = until;
+            state.done = Some(tx);
+        }
+        irq::install(IRQ_SIM, "sim", &on_interrupt).unwrap();
+        idle::set_hook(on_idle).unwrap();
+        idle::init();
+        thread::spawn(|| scheduler::start());
+
+        let result = rx.recv_timeout(Duration::from_secs(10));
+        idle::del_hook(on_idle).unwrap();
+        result.expect("virtual time stuck");
+
+        reset_kernel();
+        core::mem::take(&mut state().log)
+    }
+}
+
+/// Records `event` at the current tick.
+pub(crate) fn log(event: &'static str) {
+    let tick = clock::tick_get();
+    state().log.push((tick, event));
+}
+
+/// Forgets everything about a previous, halted, run.
+pub(crate) fn reset_kernel() {
+    host::reset();
+    scheduler::init();
+    timer::system_timer_init();
+    object::reset();
+    clock::tick_set(0);
+}
+
+fn state() -> MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Advances the virtual time as every thread is blocked.
+fn on_idle() {
+    let next = clock::tick_get() + 1;
+    let mut state = state();
+    if next > state.until {
+        let _ = state.done.take().unwrap().send(());
+        drop(state);
+        host::halt();
+    }
+    if state.schedule.contains_key(&next) {
+        host::raise(IRQ_SIM);
+    }
+    host::raise(IRQ_TICK);
+}
+
+fn on_interrupt(_irq: usize) {
+    let actions = state().schedule.remove(&clock::tick_get());
+    for mut isr in actions.into_iter().flatten() {
+        isr();
+    }
+}
+
+/// Thread `a` blocks until an interrupt wakes it up.
+fn block_until_interrupt(sim: &mut Sim, ticks: &[usize]) {
+    let n = ticks.len();
+    let a = sim.spawn("a", 1, move || {
+        for _ in 0..n {
+            log("a blocks");
+            unsafe { &mut *scheduler::current_thread() }
+                .suspend()
+                .unwrap();
+            scheduler::schedule();
+            log("a runs");
+        }
+    }) as *mut Thread as usize;
+    for &tick in ticks {
+        sim.at(tick, move || {
+            unsafe { &mut *(a as *mut Thread) }.resume().unwrap();
+            scheduler::schedule();
+        });
+    }
+}
+
+#[test]
+fn test_wake_from_interrupt() {
+    let mut sim = Sim::new(0);
+    block_until_interrupt(&mut sim, &[5]);
+    sim.spawn("b", 2, || {
+        rt_thread::sleep(3).unwrap();
+        log("b wakes");
+    });
+    assert_eq!(
+        [(0, "a blocks"), (3, "b wakes"), (5, "a runs")],
+        &*sim.run(10),
+    );
+}
+
+#[test]
+fn test_seed() {
+    fn run(seed: u64) -> (Vec<usize>, Vec<(usize, &'static str)>) {
+        let mut sim = Sim::new(seed);
+        let mut ticks = (0..4)
+            .map(|_| 1 + (sim.random() % 50) as usize)
+            .collect::<Vec<_>>();
+        ticks.sort_unstable();
+        ticks.dedup();
+        block_until_interrupt(&mut sim, &ticks);
+        (ticks, sim.run(60))
+    }
+
+    let (ticks, log) = run(42);
+    let expected = ticks
+        .iter()
+        .scan(0, |last, &tick| {
+            let blocks = core::mem::replace(last, tick);
+            Some([(blocks, "a blocks"), (tick, "a runs")])
+        })
+        .flatten()
+        .collect::<Vec<_>>();
+    assert_eq!(expected, log);
+    assert_eq!((ticks, log), run(42));
+}
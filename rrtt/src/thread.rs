@@ -1,65 +1,969 @@
-﻿use crate::{cpu::InterruptFreeGuard, list, object::Object, scheduler::CURRENT_THREAD};
-use core::ptr::NonNull;
+﻿use crate::{
+    clock,
+    cpu::{self, InterruptFreeGuard},
+    error::Error,
+    irq, list,
+    object::{Object, ObjectClassType},
+    scheduler::{self, current_thread, THREAD_DEFUNCT},
+    timer::{Timer, TIMER_FLAG_ONE_SHOT},
+    PRIORITY_MAX,
+};
+use core::{
+    mem::{align_of, size_of, size_of_val, MaybeUninit},
+    ptr::{addr_of, addr_of_mut, null_mut, NonNull},
+};
+
+/// Fill pattern of an unused thread stack
+pub(crate) const STACK_MAGIC: u8 = b'#';
+
+/// Smallest thread stack in bytes: what the port takes, see [`cpu::STACK_MIN`],
+/// and 32 words to run on.
+pub const STACK_SIZE_MIN: usize = cpu::STACK_MIN + 32 * size_of::<usize>();
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/include/rtdef.h).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ThreadState {
+    /// Initialized status
+    Init,
+    /// Ready status
+    Ready,
+    /// Suspended status
+    Suspend(SuspendFlag),
+    /// Running status
+    Running,
+    /// Closed status
+    Close,
+}
+
+/// Which early wakeups a suspended thread accepts,
+/// see [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/include/rtdef.h).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SuspendFlag {
+    /// Woken up by signals
+    Interruptible,
+    /// Woken up by kill signals only
+    Killable,
+    /// Not woken up by signals
+    Uninterruptible,
+}
 
 /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/include/rtdef.h#L479).
+#[repr(C)]
 pub struct Thread {
-    header: Object,
-    list: list::Node,
-
-    sp: NonNull<usize>,
-    entry: NonNull<usize>,
-    parameter: NonNull<usize>,
-    stack_address: NonNull<usize>,
-    stack_size: usize,
-    error: usize,
-    stat: u8,
-    current_priority: u8,
+    pub(crate) header: Object,
+    pub(crate) list: list::Node,
+
+    pub(crate) sp: NonNull<usize>,
+    entry: extern "C" fn(usize),
+    parameter: usize,
+    pub(crate) stack_address: NonNull<usize>,
+    pub(crate) stack_size: usize,
+    pub(crate) error: Result<(), Error>,
+    pub(crate) stat: ThreadState,
+    pub(crate) current_priority: u8,
     init_priority: u8,
-    number_mask: usize,
+    #[cfg(large_priority)]
+    pub(crate) number: u8,
+    #[cfg(large_priority)]
+    pub(crate) high_mask: u8,
+    pub(crate) number_mask: usize,
     #[cfg(feature = "event")]
     event_size: u32,
     #[cfg(feature = "event")]
     event_info: u8,
-    init_tick: usize,
-    remain_tick: usize,
-    // TODO TIMER
+    pub(crate) init_tick: usize,
+    pub(crate) remain_tick: usize,
+    thread_timer: Timer,
     cleanup: Option<fn(NonNull<Thread>)>,
-    user_data: u32,
+    user_data: usize,
+    pub(crate) tls: [usize; crate::tls::TLS_MAX],
+    joinable: bool,
+    joiner: *mut Thread,
+    exit_code: usize,
+    #[cfg(feature = "signals")]
+    pub(crate) sig_pending: u32,
+    /// signals not blocked, see [`crate::signal::unmask`]
+    #[cfg(feature = "signals")]
+    pub(crate) sig_mask: u32,
+    #[cfg(feature = "signals")]
+    pub(crate) sig_stat: u8,
+    #[cfg(feature = "signals")]
+    pub(crate) sig_ret: NonNull<usize>,
+    #[cfg(feature = "signals")]
+    pub(crate) sig_vectors: [Option<fn(usize)>; crate::signal::SIG_MAX],
+}
+
+impl Thread {
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+    ///
+    /// Panics if `stack` is smaller than [`STACK_SIZE_MIN`] or `tick` is 0.
+    pub fn init(
+        thread: &mut MaybeUninit<Self>,
+        name: &str,
+        entry: extern "C" fn(usize),
+        parameter: usize,
+        stack: &'static mut [usize],
+        priority: u8,
+        tick: usize,
+    ) {
+        let ptr = thread.as_mut_ptr();
+        Object::init(
+            unsafe { &mut *addr_of_mut!((*ptr).header).cast() },
+            ObjectClassType::Thread,
+            name,
+        );
+        let stack_size = size_of_val(stack);
+        unsafe {
+            Self::init_inner(
+                ptr,
+                name,
+                entry,
+                parameter,
+                NonNull::slice_from_raw_parts(NonNull::from(stack).cast(), stack_size),
+                priority,
+                tick,
+            )
+        };
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+    ///
+    /// Returns [`Error::Inval`] if `stack_size` is below [`STACK_SIZE_MIN`] or `tick` is 0.
+    #[cfg(feature = "mem-heap")]
+    pub fn create(
+        name: &str,
+        entry: extern "C" fn(usize),
+        parameter: usize,
+        stack_size: usize,
+        priority: u8,
+        tick: usize,
+    ) -> Result<&'static mut Self, Error> {
+        if stack_size < STACK_SIZE_MIN || tick == 0 {
+            return Err(Error::Inval);
+        }
+        let object = Object::allocate(ObjectClassType::Thread, name).ok_or(Error::NoMem)?;
+        let Some(stack) = NonNull::new(unsafe { alloc::alloc::alloc(stack_layout(stack_size)) })
+        else {
+            unsafe { Object::delete(object) };
+            return Err(Error::NoMem);
+        };
+        let ptr = object.as_ptr().cast::<Self>();
+        unsafe {
+            Self::init_inner(
+                ptr,
+                name,
+                entry,
+                parameter,
+                NonNull::slice_from_raw_parts(stack, stack_size),
+                priority,
+                tick,
+            );
+            Ok(&mut *ptr)
+        }
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+    #[cfg(feature = "mem-heap")]
+    pub fn delete(&mut self) {
+        assert!(!self.header.is_system_object());
+
+        if self.stat != ThreadState::Init {
+            scheduler::remove_thread(self);
+        }
+        thread_cleanup_execute(self);
+        self.thread_timer.detach();
+        self.stat = ThreadState::Close;
+
+        if !self.joinable {
+            release(self);
+        }
+    }
+
+    /// Initializes and starts a thread running `f`.
+    ///
+    /// `f` is moved to the top of `stack`, so the thread needs no other storage;
+    /// the rest of `stack` is used as the thread stack.
+    /// Returning from `f` exits the thread with the [`ExitCode`] of what it returns,
+    /// which [`Thread::join`] collects if `joinable`.
+    pub fn spawn_static<F, R>(
+        thread: &'static mut MaybeUninit<Self>,
+        name: &str,
+        stack: &'static mut [usize],
+        priority: u8,
+        tick: usize,
+        joinable: bool,
+        f: F,
+    ) -> &'static mut Self
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: ExitCode,
+    {
+        let bottom = stack.as_mut_ptr() as usize;
+        let top = bottom + size_of_val(stack);
+        let closure = (top - size_of::<F>()) & !(align_of::<F>() - 1);
+        assert!(closure > bottom);
+        unsafe { (closure as *mut F).write(f) };
+        let stack = unsafe {
+            core::slice::from_raw_parts_mut(
+                stack.as_mut_ptr(),
+                (closure - bottom) / size_of::<usize>(),
+            )
+        };
+
+        Self::init(
+            thread,
+            name,
+            closure_entry::<F, R>,
+            closure,
+            stack,
+            priority,
+            tick,
+        );
+        let thread = unsafe { thread.assume_init_mut() };
+        thread.joinable = joinable;
+        thread.startup();
+        thread
+    }
+
+    /// Creates and starts a thread running `f`, which is boxed in the kernel heap.
+    ///
+    /// Returning from `f` exits the thread as with [`Thread::spawn_static`].
+    #[cfg(feature = "mem-heap")]
+    pub fn spawn<F, R>(
+        name: &str,
+        stack_size: usize,
+        priority: u8,
+        tick: usize,
+        joinable: bool,
+        f: F,
+    ) -> Result<&'static mut Self, Error>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: ExitCode,
+    {
+        use alloc::boxed::Box;
+
+        let closure = Box::into_raw(Box::new(f));
+        match Self::create(
+            name,
+            boxed_closure_entry::<F, R>,
+            closure as usize,
+            stack_size,
+            priority,
+            tick,
+        ) {
+            Ok(thread) => {
+                thread.joinable = joinable;
+                thread.startup();
+                Ok(thread)
+            }
+            Err(e) => {
+                drop(unsafe { Box::from_raw(closure) });
+                Err(e)
+            }
+        }
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+    pub fn startup(&mut self) {
+        assert_eq!(self.stat, ThreadState::Init);
+
+        self.set_current_priority(self.init_priority);
+
+        self.stat = ThreadState::Suspend(SuspendFlag::Uninterruptible);
+        let _ = self.resume();
+
+        if !current_thread().is_null() {
+            scheduler::schedule();
+        }
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+    #[inline]
+    pub fn suspend(&mut self) -> Result<(), Error> {
+        self.suspend_with_flag(SuspendFlag::Uninterruptible)
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rt-thread/blob/v5.1.0/src/thread.c).
+    ///
+    /// `flag` decides whether a signal may wake the thread up early,
+    /// in which case it is taken off any wait list and sees [`Error::Intr`].
+    pub fn suspend_with_flag(&mut self, flag: SuspendFlag) -> Result<(), Error> {
+        if self.stat != ThreadState::Ready {
+            return Err(Error::Generic);
+        }
+
+        let _guard = InterruptFreeGuard::new();
+        self.stat = ThreadState::Suspend(flag);
+        scheduler::remove_thread(self);
+        let _ = self.thread_timer.stop();
+        Ok(())
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+    pub fn resume(&mut self) -> Result<(), Error> {
+        if !matches!(self.stat, ThreadState::Suspend(_)) {
+            return Err(Error::Generic);
+        }
+
+        {
+            let _guard = InterruptFreeGuard::new();
+            self.list.remove();
+            let _ = self.thread_timer.stop();
+        }
+        scheduler::insert_thread(self);
+        Ok(())
+    }
+
+    /// Makes the thread joinable, before [`Thread::startup`];
+    /// [`Thread::spawn_static`] and [`Thread::spawn`] take it as an argument instead.
+    ///
+    /// A joinable thread keeps its control block after exit
+    /// until another thread collects its exit code with [`Thread::join`].
+    pub fn set_joinable(&mut self) {
+        assert_eq!(self.stat, ThreadState::Init);
+        self.joinable = true;
+    }
+
+    /// Blocks until the joinable thread exits and returns its exit code.
+    ///
+    /// The thread is detached or handed to the idle thread afterwards,
+    /// so it must not be used once this returns `Ok`.
+    /// Returns [`Error::Inval`] if the thread is not joinable or is the current thread,
+    /// [`Error::Busy`] if another thread is already joining it,
+    /// or the error of the current thread if it is woken up before the target exits.
+    pub fn join(&mut self) -> Result<usize, Error> {
+        irq::check_thread_context()?;
+        let current = unsafe { &mut *current_thread() };
+        if !self.joinable || core::ptr::eq(current, self) {
+            return Err(Error::Inval);
+        }
+
+        let guard = InterruptFreeGuard::new();
+        if self.stat != ThreadState::Close {
+            if !self.joiner.is_null() {
+                return Err(Error::Busy);
+            }
+            current.suspend_with_flag(SuspendFlag::Interruptible)?;
+            current.error = Err(Error::Intr);
+            self.joiner = current;
+            drop(guard);
+
+            scheduler::schedule();
+
+            let _guard = InterruptFreeGuard::new();
+            if self.stat != ThreadState::Close {
+                self.joiner = null_mut();
+                return Err(current.error.err().unwrap_or(Error::Intr));
+            }
+        }
+
+        let code = self.exit_code;
+        release(self);
+        Ok(code)
+    }
+
+    /// A word for the application, `0` until set; see [`crate::tls`] for typed storage.
+    #[inline]
+    pub fn user_data(&self) -> usize {
+        self.user_data
+    }
+
+    /// Sets the word returned by [`Thread::user_data`].
+    #[inline]
+    pub fn set_user_data(&mut self, user_data: usize) {
+        self.user_data = user_data;
+    }
+
+    /// Current state of the thread.
+    #[inline]
+    pub fn stat(&self) -> ThreadState {
+        self.stat
+    }
+
+    /// Returns the maximum number of stack bytes this thread has ever used.
+    ///
+    /// Counted from the untouched fill pattern at the bottom of the stack,
+    /// as `list_thread` does in [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/kservice.c).
+    pub fn stack_high_water(&self) -> usize {
+        let stack = unsafe {
+            core::slice::from_raw_parts(self.stack_address.as_ptr().cast::<u8>(), self.stack_size)
+        };
+        self.stack_size - stack.iter().take_while(|&&b| b == STACK_MAGIC).count()
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+    ///
+    /// The new priority takes effect at the next scheduling point.
+    pub fn set_priority(&mut self, priority: u8) {
+        assert!((priority as usize) < PRIORITY_MAX);
+
+        let _guard = InterruptFreeGuard::new();
+        if self.stat == ThreadState::Ready {
+            scheduler::remove_thread(self);
+            self.set_current_priority(priority);
+            scheduler::insert_thread(self);
+        } else {
+            self.set_current_priority(priority);
+        }
+    }
+
+    /// Sets `current_priority` and recomputes the ready table masks from it.
+    fn set_current_priority(&mut self, priority: u8) {
+        self.current_priority = priority;
+        #[cfg(large_priority)]
+        {
+            self.number = priority >> 3;
+            self.number_mask = 1 << self.number;
+            self.high_mask = 1 << (priority & 0x07);
+        }
+        #[cfg(small_priority)]
+        {
+            self.number_mask = 1 << priority;
+        }
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+    ///
+    /// Only the header of `thread` has to be initialized,
+    /// the other fields are written in place without reading them.
+    unsafe fn init_inner(
+        thread: *mut Self,
+        name: &str,
+        entry: extern "C" fn(usize),
+        parameter: usize,
+        stack: NonNull<[u8]>,
+        priority: u8,
+        tick: usize,
+    ) {
+        assert!((priority as usize) < PRIORITY_MAX);
+        assert!(stack.len() >= STACK_SIZE_MIN);
+        assert!(tick > 0);
+        let stack_address = stack.cast::<usize>();
+        let stack_size = stack.len();
+
+        stack_address
+            .as_ptr()
+            .cast::<u8>()
+            .write_bytes(STACK_MAGIC, stack_size);
+        let sp = NonNull::new_unchecked(
+            cpu::stack_init(
+                entry,
+                parameter,
+                stack_address
+                    .as_ptr()
+                    .cast::<u8>()
+                    .add(stack_size - size_of::<usize>()),
+                thread_exit,
+            )
+            .cast(),
+        );
+
+        macro_rules! write_fields {
+            ($($(#[$attr:meta])* $field:ident: $value:expr,)*) => {
+                $($(#[$attr])* addr_of_mut!((*thread).$field).write($value);)*
+            };
+        }
+        write_fields! {
+            list: list::Node::new_empty(addr_of!((*thread).list)),
+            sp: sp,
+            entry: entry,
+            parameter: parameter,
+            stack_address: stack_address,
+            stack_size: stack_size,
+            error: Ok(()),
+            stat: ThreadState::Init,
+            current_priority: priority,
+            init_priority: priority,
+            #[cfg(large_priority)]
+            number: 0,
+            #[cfg(large_priority)]
+            high_mask: 0,
+            number_mask: 0,
+            #[cfg(feature = "event")]
+            event_size: 0,
+            #[cfg(feature = "event")]
+            event_info: 0,
+            init_tick: tick,
+            remain_tick: tick,
+            cleanup: None,
+            user_data: 0,
+            tls: [0; crate::tls::TLS_MAX],
+            joinable: false,
+            joiner: null_mut(),
+            exit_code: 0,
+            #[cfg(feature = "signals")]
+            sig_pending: 0,
+            #[cfg(feature = "signals")]
+            sig_mask: 0,
+            #[cfg(feature = "signals")]
+            sig_stat: 0,
+            #[cfg(feature = "signals")]
+            sig_ret: sp,
+            #[cfg(feature = "signals")]
+            sig_vectors: [None; crate::signal::SIG_MAX],
+        }
+        Timer::init(
+            &mut *addr_of_mut!((*thread).thread_timer).cast(),
+            name,
+            thread_timeout,
+            thread as usize,
+            0,
+            TIMER_FLAG_ONE_SHOT,
+        );
+    }
+}
+
+/// Memory layout of a thread stack allocated from the kernel heap.
+#[cfg(feature = "mem-heap")]
+#[inline]
+pub(crate) fn stack_layout(stack_size: usize) -> core::alloc::Layout {
+    core::alloc::Layout::from_size_align(stack_size, crate::ALIGN_SIZE).unwrap()
 }
 
 /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c#L83).
 fn thread_cleanup_execute(thread: &mut Thread) {
+    crate::tls::destruct(thread);
+
     let _guard = InterruptFreeGuard::new();
     if let Some(cleanup) = thread.cleanup {
-        cleanup(NonNull::from(thread));
+        cleanup(NonNull::from(&mut *thread));
+    }
+    if let Some(joiner) = unsafe { thread.joiner.as_mut() } {
+        thread.joiner = null_mut();
+        joiner.error = Ok(());
+        let _ = joiner.resume();
     }
 }
 
-pub fn exit() {
-    let thread = unsafe { &mut *CURRENT_THREAD };
+/// Gives back a closed thread: detaches a static one, or lets the idle thread free a dynamic one.
+fn release(thread: &mut Thread) {
     let _guard = InterruptFreeGuard::new();
+    if thread.header.is_system_object() {
+        thread.header.detach();
+    } else {
+        THREAD_DEFUNCT
+            .with(|defunct| unsafe { defunct.assume_init_mut() }.insert(&mut thread.list));
+    }
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+fn thread_timeout(parameter: usize) {
+    let thread = unsafe { &mut *(parameter as *mut Thread) };
+    assert!(matches!(thread.stat, ThreadState::Suspend(_)));
+
+    thread.error = Err(Error::Timeout);
+    thread.list.remove();
+    scheduler::insert_thread(thread);
+    scheduler::schedule();
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+///
+/// Returns [`Error::Intr`] if the thread is resumed before `tick` elapses.
+pub fn sleep(tick: usize) -> Result<(), Error> {
+    irq::check_thread_context()?;
+    let thread = unsafe { &mut *current_thread() };
+    {
+        let _guard = InterruptFreeGuard::new();
+        thread.suspend_with_flag(SuspendFlag::Interruptible)?;
+        thread.thread_timer.set_time(tick);
+        let _ = thread.thread_timer.start();
+        thread.error = Err(Error::Intr);
+    }
+    scheduler::schedule();
+
+    if thread.error == Err(Error::Timeout) {
+        thread.error = Ok(());
+    }
+    thread.error
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+#[inline]
+pub fn mdelay(ms: i32) -> Result<(), Error> {
+    sleep(clock::tick_from_millisecond(ms))
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+///
+/// Sleeps until `*tick + inc_tick` and advances `*tick` by `inc_tick`,
+/// so a periodic task does not drift with the time spent in each period.
+pub fn delay_until(tick: &mut usize, inc_tick: usize) -> Result<(), Error> {
+    irq::check_thread_context()?;
+    let thread = unsafe { &mut *current_thread() };
+    {
+        let _guard = InterruptFreeGuard::new();
+        let cur_tick = clock::tick_get();
+        if cur_tick.wrapping_sub(*tick) >= inc_tick {
+            *tick = cur_tick;
+            thread.error = Ok(());
+            return Ok(());
+        }
+
+        thread.suspend_with_flag(SuspendFlag::Interruptible)?;
+        *tick = tick.wrapping_add(inc_tick);
+        thread.thread_timer.set_time(tick.wrapping_sub(cur_tick));
+        let _ = thread.thread_timer.start();
+        thread.error = Err(Error::Intr);
+    }
+    scheduler::schedule();
+
+    if thread.error == Err(Error::Timeout) {
+        thread.error = Ok(());
+    }
+    thread.error
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+pub fn yield_now() {
+    let thread = unsafe { &mut *current_thread() };
+    {
+        let _guard = InterruptFreeGuard::new();
+        if thread.stat != ThreadState::Ready || thread.list.next == thread.list.prev {
+            return;
+        }
+        scheduler::remove_thread(thread);
+        scheduler::insert_thread(thread);
+    }
+    scheduler::schedule();
+}
+
+/// What a thread closure returns, the exit code of the thread.
+pub trait ExitCode {
+    fn exit_code(self) -> usize;
+}
+
+impl ExitCode for () {
+    #[inline]
+    fn exit_code(self) -> usize {
+        0
+    }
+}
+
+impl ExitCode for usize {
+    #[inline]
+    fn exit_code(self) -> usize {
+        self
+    }
+}
+
+/// Entry of threads from [`Thread::spawn_static`].
+extern "C" fn closure_entry<F: FnOnce() -> R, R: ExitCode>(parameter: usize) {
+    let f = unsafe { (parameter as *mut F).read() };
+    exit_with(f().exit_code())
+}
+
+/// Entry of threads from [`Thread::spawn`].
+#[cfg(feature = "mem-heap")]
+extern "C" fn boxed_closure_entry<F: FnOnce() -> R, R: ExitCode>(parameter: usize) {
+    let f = unsafe { alloc::boxed::Box::from_raw(parameter as *mut F) };
+    exit_with(f().exit_code())
+}
+
+/// The return address of every thread entry.
+pub(crate) extern "C" fn thread_exit() -> ! {
+    exit()
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/thread.c).
+#[inline]
+pub fn exit() -> ! {
+    exit_with(0)
+}
+
+/// Exits the current thread with `code`, which [`Thread::join`] returns.
+pub fn exit_with(code: usize) -> ! {
+    let thread = unsafe { &mut *current_thread() };
+    let guard = InterruptFreeGuard::new();
+    thread.exit_code = code;
     thread_cleanup_execute(thread);
+
+    scheduler::remove_thread(thread);
+    thread.stat = ThreadState::Close;
+
+    thread.thread_timer.detach();
+
+    if !thread.joinable {
+        release(thread);
+    }
+    drop(guard);
+
+    // a switch out of an interrupt or a locked scheduler is only deferred
+    loop {
+        scheduler::schedule();
+    }
+}
+
+#[cfg(feature = "mem-heap")]
+#[test]
+fn test_create_delete() {
+    extern "C" fn entry(_: usize) {}
+
+    let _lock = crate::test_lock();
+    crate::sim::reset_kernel();
+    assert_eq!(
+        Thread::create("small", entry, 0, size_of::<usize>(), 1, 10).err(),
+        Some(Error::Inval),
+    );
+
+    let thread = Thread::create("dyn", entry, 0, STACK_SIZE_MIN, 1, 10).unwrap();
+    assert!(!thread.header.is_system_object());
+    assert_eq!(thread.stat(), ThreadState::Init);
+    assert!(Object::find("dyn", ObjectClassType::Thread).is_some());
+
+    thread.delete();
+    // freed by the idle thread
+    assert!(Object::find("dyn", ObjectClassType::Thread).is_some());
+    crate::idle::excute();
+    assert!(Object::find("dyn", ObjectClassType::Thread).is_none());
+
+    crate::sim::reset_kernel();
 }
 
-//     _thread_cleanup_execute(thread);
+#[test]
+fn test_exit() {
+    use crate::sim::{log, Sim};
 
-//     /* remove from schedule */
-//     rt_schedule_remove_thread(thread);
-//     /* change stat */
-//     thread->stat = RT_THREAD_CLOSE;
+    let mut sim = Sim::new(0);
+    sim.spawn("a", 1, || log("a returns"));
+    sim.spawn("b", 2, || {
+        if Object::find("a", ObjectClassType::Thread).is_none() {
+            log("a detached");
+        }
+        exit();
+    });
+    assert_eq!([(0, "a returns"), (0, "a detached")], &*sim.run(1));
+}
+
+#[test]
+fn test_suspend_resume() {
+    use crate::sim::{log, Sim};
+    use std::boxed::Box;
+
+    let mut sim = Sim::new(0);
+    let a = sim.spawn("a", 1, || {
+        log("a suspends");
+        unsafe { &mut *current_thread() }.suspend().unwrap();
+        scheduler::schedule();
+        log("a resumed");
+    }) as *mut Thread as usize;
+    sim.spawn("b", 2, move || {
+        let a = unsafe { &mut *(a as *mut Thread) };
+        if a.stat() == ThreadState::Suspend(SuspendFlag::Uninterruptible)
+            && a.suspend() == Err(Error::Generic)
+        {
+            log("a suspended");
+        }
+        a.resume().unwrap();
+        log("b resumes a");
+        scheduler::schedule();
+
+        // started by a running thread, a higher priority thread runs at once
+        let c = Box::leak(Box::new(MaybeUninit::uninit()));
+        Thread::init(c, "c", c_entry, 0, Box::leak(Box::new([0; 256])), 0, 10);
+        unsafe { c.assume_init_mut() }.startup();
+        log("b starts c");
+    });
+    extern "C" fn c_entry(_: usize) {
+        log("c runs");
+    }
+    assert_eq!(
+        [
+            (0, "a suspends"),
+            (0, "a suspended"),
+            (0, "b resumes a"),
+            (0, "a resumed"),
+            (0, "c runs"),
+            (0, "b starts c"),
+        ],
+        &*sim.run(1),
+    );
+}
+
+#[test]
+fn test_yield_and_priority() {
+    use crate::sim::{log, Sim};
+
+    let mut sim = Sim::new(0);
+    let c = sim.spawn("c", 3, || log("c runs")) as *mut Thread as usize;
+    sim.spawn("a", 2, move || {
+        log("a1");
+        yield_now();
+        log("a2");
+        // c is ready and now the highest
+        unsafe { &mut *(c as *mut Thread) }.set_priority(1);
+        scheduler::schedule();
+        log("a3");
+    });
+    sim.spawn("b", 2, || {
+        log("b1");
+        yield_now();
+        log("b2");
+    });
+    assert_eq!(
+        [
+            (0, "a1"),
+            (0, "b1"),
+            (0, "a2"),
+            (0, "c runs"),
+            (0, "a3"),
+            (0, "b2"),
+        ],
+        &*sim.run(1),
+    );
+}
 
-//     /* remove it from timer list */
-//     rt_timer_detach(&thread->thread_timer);
+#[test]
+fn test_time_slice() {
+    extern "C" fn entry(_: usize) {}
 
-//     if (rt_object_is_systemobject((rt_object_t)thread) == RT_TRUE)
-//     {
-//         rt_object_detach((rt_object_t)thread);
-//     }
-//     else
-//     {
-//         /* insert to defunct thread list */
-//         rt_list_insert_after(&rt_thread_defunct, &(thread->tlist));
-//     }
+    let _lock = crate::test_lock();
+    crate::sim::reset_kernel();
+    let thread = std::boxed::Box::leak(std::boxed::Box::new(MaybeUninit::uninit()));
+    Thread::init(
+        thread,
+        "slice",
+        entry,
+        0,
+        std::boxed::Box::leak(std::boxed::Box::new([0; 64])),
+        1,
+        3,
+    );
+    let thread = unsafe { thread.assume_init_mut() };
+    scheduler::CURRENT_THREAD.with(|current| *current = thread);
 
-//     /* switch to next task */
-//     rt_schedule();
+    clock::tick_increase();
+    clock::tick_increase();
+    assert_eq!(thread.remain_tick, 1);
+    clock::tick_increase();
+    assert_eq!(thread.remain_tick, 3);
+
+    crate::sim::reset_kernel();
+}
+
+#[test]
+fn test_stack_high_water() {
+    extern "C" fn entry(_: usize) {}
+
+    let _lock = crate::test_lock();
+    crate::object::reset();
+    let thread = std::boxed::Box::leak(std::boxed::Box::new(MaybeUninit::uninit()));
+    let stack = std::boxed::Box::leak(std::boxed::Box::new([0usize; 64]));
+    let base = stack.as_mut_ptr().cast::<u8>();
+    Thread::init(thread, "water", entry, 0, stack, 1, 10);
+    let thread = unsafe { thread.assume_init_mut() };
+
+    // only the initial frame
+    let frame = thread.stack_high_water();
+    assert!(frame > 0 && frame <= cpu::STACK_MIN);
+    assert_eq!(unsafe { *base }, STACK_MAGIC);
+
+    unsafe { *base.add(thread.stack_size / 2) = 0 };
+    assert_eq!(thread.stack_high_water(), thread.stack_size / 2);
+
+    thread.header.detach();
+    crate::object::reset();
+}
+
+#[test]
+fn test_spawn() {
+    use crate::sim::{log, Sim};
+    use std::{boxed::Box, sync::Arc};
+
+    let mut sim = Sim::new(0);
+    let captured = Arc::new("static runs");
+    let moved = captured.clone();
+    Thread::spawn_static(
+        Box::leak(Box::new(MaybeUninit::uninit())),
+        "static",
+        Box::leak(Box::new([0; 256])),
+        1,
+        10,
+        false,
+        move || log(*moved),
+    );
+    #[cfg(feature = "mem-heap")]
+    {
+        let moved = captured.clone();
+        assert_eq!(
+            Thread::spawn("small", 0, 2, 10, false, move || log(*moved)).err(),
+            Some(Error::Inval),
+        );
+        let moved = captured.clone();
+        Thread::spawn("heap", 1024, 2, 10, false, move || {
+            drop(moved);
+            log("heap runs")
+        })
+        .unwrap();
+    }
+    let log = sim.run(1);
+    assert_eq!((0, "static runs"), log[0]);
+    #[cfg(feature = "mem-heap")]
+    assert_eq!((0, "heap runs"), log[1]);
+    // every closure is dropped, run or not
+    assert_eq!(Arc::strong_count(&captured), 1);
+}
+
+#[test]
+fn test_join() {
+    use crate::sim::{log, Sim};
+    use std::boxed::Box;
+
+    fn spawn_worker(
+        name: &str,
+        priority: u8,
+        joinable: bool,
+        ticks: usize,
+        code: usize,
+    ) -> &'static mut Thread {
+        Thread::spawn_static(
+            Box::leak(Box::new(MaybeUninit::uninit())),
+            name,
+            Box::leak(Box::new([0; 256])),
+            priority,
+            10,
+            joinable,
+            move || {
+                if ticks > 0 {
+                    sleep(ticks).unwrap();
+                }
+                code
+            },
+        )
+    }
+
+    let mut sim = Sim::new(0);
+    sim.spawn("main", 2, || {
+        if unsafe { &mut *current_thread() }.join() == Err(Error::Inval) {
+            log("self rejected");
+        }
+        let detached = spawn_worker("detached", 3, false, 0, 0);
+        if detached.join() == Err(Error::Inval) {
+            log("detached rejected");
+        }
+        // exits before the join
+        let first = spawn_worker("first", 1, true, 0, 7);
+        if first.join() == Ok(7) {
+            log("first joined");
+        }
+        // exits while joined
+        let second = spawn_worker("second", 3, true, 5, 5);
+        if second.join() == Ok(5) {
+            log("second joined");
+        }
+        if Object::find("second", ObjectClassType::Thread).is_none() {
+            log("second detached");
+        }
+    });
+    assert_eq!(
+        [
+            (0, "self rejected"),
+            (0, "detached rejected"),
+            (0, "first joined"),
+            (5, "second joined"),
+            (5, "second detached"),
+        ],
+        &*sim.run(10),
+    );
+}
@@ -0,0 +1,217 @@
+use crate::{
+    clock,
+    cpu::{self, InterruptFreeCell},
+    error::Error,
+    list,
+    object::{Object, ObjectClassType},
+};
+use core::{
+    mem::MaybeUninit,
+    ptr::{addr_of, addr_of_mut, NonNull},
+};
+
+/// Timer is deactive
+pub const TIMER_FLAG_DEACTIVATED: u8 = 0x0;
+/// Timer is active
+pub const TIMER_FLAG_ACTIVATED: u8 = 0x1;
+/// One shot timer
+pub const TIMER_FLAG_ONE_SHOT: u8 = 0x0;
+/// Periodic timer
+pub const TIMER_FLAG_PERIODIC: u8 = 0x2;
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/timer.c).
+static TIMER_LIST: InterruptFreeCell<MaybeUninit<list::Node>> =
+    InterruptFreeCell::new(MaybeUninit::uninit());
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/include/rtdef.h).
+#[repr(C)]
+pub struct Timer {
+    header: Object,
+    row: list::Node,
+
+    timeout_func: fn(usize),
+    parameter: usize,
+    init_tick: usize,
+    timeout_tick: usize,
+}
+
+impl Timer {
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/timer.c).
+    pub fn init(
+        timer: &mut MaybeUninit<Self>,
+        name: &str,
+        timeout: fn(usize),
+        parameter: usize,
+        time: usize,
+        flag: u8,
+    ) {
+        let ptr = timer.as_mut_ptr();
+        Object::init(
+            unsafe { &mut *addr_of_mut!((*ptr).header).cast() },
+            ObjectClassType::Timer,
+            name,
+        );
+        // the other fields may be uninitialized, so they are written without being read
+        unsafe {
+            (*ptr).header.flag = flag & !TIMER_FLAG_ACTIVATED;
+            addr_of_mut!((*ptr).row).write(list::Node::new_empty(addr_of!((*ptr).row)));
+            addr_of_mut!((*ptr).timeout_func).write(timeout);
+            addr_of_mut!((*ptr).parameter).write(parameter);
+            addr_of_mut!((*ptr).init_tick).write(time);
+            addr_of_mut!((*ptr).timeout_tick).write(0);
+        }
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/timer.c).
+    pub fn detach(&mut self) {
+        {
+            let _guard = cpu::InterruptFreeGuard::new();
+            self.row.remove();
+            self.header.flag &= !TIMER_FLAG_ACTIVATED;
+        }
+        self.header.detach();
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/timer.c).
+    pub fn start(&mut self) -> Result<(), Error> {
+        let _guard = cpu::InterruptFreeGuard::new();
+        self.row.remove();
+        self.header.flag &= !TIMER_FLAG_ACTIVATED;
+
+        assert!(self.init_tick < usize::MAX / 2);
+        self.timeout_tick = clock::tick_get().wrapping_add(self.init_tick);
+
+        TIMER_LIST.with(|head| {
+            let head = unsafe { head.assume_init_mut() };
+            let mut pos = NonNull::from(&mut *head);
+            for node in &*head {
+                let t = unsafe { &*container_of!(node, Timer, row) };
+                let diff = t.timeout_tick.wrapping_sub(self.timeout_tick);
+                if diff != 0 && diff < usize::MAX / 2 {
+                    pos = NonNull::from(node);
+                    break;
+                }
+            }
+            unsafe { pos.as_mut() }.insert_before(&mut self.row);
+        });
+
+        self.header.flag |= TIMER_FLAG_ACTIVATED;
+        Ok(())
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/timer.c).
+    pub fn stop(&mut self) -> Result<(), Error> {
+        if self.header.flag & TIMER_FLAG_ACTIVATED == 0 {
+            return Err(Error::Generic);
+        }
+
+        let _guard = cpu::InterruptFreeGuard::new();
+        self.row.remove();
+        self.header.flag &= !TIMER_FLAG_ACTIVATED;
+        Ok(())
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/timer.c).
+    #[inline]
+    pub fn set_time(&mut self, time: usize) {
+        self.init_tick = time;
+    }
+
+    /// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/timer.c).
+    #[inline]
+    pub fn get_time(&self) -> usize {
+        self.init_tick
+    }
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/timer.c).
+pub fn check() {
+    let mut current_tick = clock::tick_get();
+
+    let _guard = cpu::InterruptFreeGuard::new();
+    // the list is not held across the timeout functions, which may start timers
+    while let Some(next) = TIMER_LIST.with(|head| {
+        let head = unsafe { head.assume_init_ref() };
+        (!head.is_empty()).then_some(head.next)
+    }) {
+        let t = unsafe { &mut *container_of!(next.as_ptr(), Timer, row).cast_mut() };
+        if current_tick.wrapping_sub(t.timeout_tick) >= usize::MAX / 2 {
+            break;
+        }
+
+        t.row.remove();
+        if t.header.flag & TIMER_FLAG_PERIODIC == 0 {
+            t.header.flag &= !TIMER_FLAG_ACTIVATED;
+        }
+        // the timeout function may stop or restart the timer
+        let mut list = MaybeUninit::<list::Node>::uninit();
+        list.write(unsafe { list::Node::new_empty(list.as_ptr()) });
+        unsafe { list.assume_init_mut() }.insert(&mut t.row);
+        (t.timeout_func)(t.parameter);
+
+        current_tick = clock::tick_get();
+        if unsafe { list.assume_init_ref() }.is_empty() {
+            continue;
+        }
+        t.row.remove();
+        if t.header.flag & TIMER_FLAG_PERIODIC != 0 && t.header.flag & TIMER_FLAG_ACTIVATED != 0 {
+            t.header.flag &= !TIMER_FLAG_ACTIVATED;
+            let _ = t.start();
+        }
+    }
+}
+
+/// See [the c code](https://github.com/RT-Thread/rtthread-nano/blob/9177e3e2f61794205565b2c53b0cb4ed2abcc43b/rt-thread/src/timer.c).
+pub fn system_timer_init() {
+    TIMER_LIST.with(|head| {
+        head.write(unsafe { list::Node::new_empty(head.as_ptr()) });
+    });
+}
+
+#[test]
+fn test_check() {
+    use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+    static FIRED: [AtomicUsize; 3] = [
+        AtomicUsize::new(0),
+        AtomicUsize::new(0),
+        AtomicUsize::new(0),
+    ];
+    static ORDER: AtomicUsize = AtomicUsize::new(0);
+
+    fn timeout(i: usize) {
+        FIRED[i].fetch_add(1, Relaxed);
+        ORDER.store(ORDER.load(Relaxed) * 10 + i + 1, Relaxed);
+    }
+
+    let _lock = crate::test_lock();
+    system_timer_init();
+    clock::tick_set(0);
+
+    let mut timers = unsafe { MaybeUninit::<[MaybeUninit<Timer>; 3]>::uninit().assume_init() };
+    Timer::init(&mut timers[0], "t0", timeout, 0, 5, TIMER_FLAG_ONE_SHOT);
+    Timer::init(&mut timers[1], "t1", timeout, 1, 3, TIMER_FLAG_PERIODIC);
+    Timer::init(&mut timers[2], "t2", timeout, 2, 5, TIMER_FLAG_ONE_SHOT);
+    let [t0, t1, t2] = unsafe { timers.each_mut().map(|t| t.assume_init_mut()) };
+    t0.start().unwrap();
+    t1.start().unwrap();
+    t2.start().unwrap();
+
+    clock::tick_set(2);
+    check();
+    assert_eq!(0, ORDER.load(Relaxed));
+
+    clock::tick_set(5);
+    check();
+    assert_eq!(213, ORDER.load(Relaxed));
+    assert_eq!(Err(Error::Generic), t0.stop());
+    assert_eq!(Ok(()), t1.stop());
+
+    clock::tick_set(100);
+    check();
+    assert_eq!([1, 1, 1], FIRED.each_ref().map(|n| n.load(Relaxed)));
+
+    t0.detach();
+    t1.detach();
+    t2.detach();
+}
@@ -0,0 +1,214 @@
+use crate::{
+    cpu::{InterruptFreeCell, InterruptFreeGuard},
+    error::Error,
+    scheduler::current_thread,
+    thread::Thread,
+};
+
+/// Number of thread-local storage slots of a thread
+pub const TLS_MAX: usize = 4;
+
+const _: () = assert!(TLS_MAX <= u32::BITS as usize);
+
+static KEY_USED: InterruptFreeCell<u32> = InterruptFreeCell::new(0);
+type Destructors = [Option<fn(usize)>; TLS_MAX];
+
+static DESTRUCTORS: InterruptFreeCell<Destructors> = InterruptFreeCell::new([None; TLS_MAX]);
+
+/// A thread-local storage slot, holding a `usize` for every thread.
+///
+/// Slots are meant for static keys and are never given back.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Key(usize);
+
+impl Key {
+    /// Takes a free slot, which reads `0` in every thread.
+    ///
+    /// `destructor` is called with the value of each thread that exits with a non-zero value.
+    /// Returns [`Error::Full`] if all [`TLS_MAX`] slots are taken.
+    pub fn create(destructor: Option<fn(usize)>) -> Result<Self, Error> {
+        let index = KEY_USED.with(|used| {
+            let index = (!*used).trailing_zeros() as usize;
+            if index < TLS_MAX {
+                *used |= 1 << index;
+            }
+            index
+        });
+        if index >= TLS_MAX {
+            return Err(Error::Full);
+        }
+        DESTRUCTORS.with(|destructors| destructors[index] = destructor);
+        Ok(Self(index))
+    }
+
+    /// Value of the slot in the current thread.
+    #[inline]
+    pub fn get(self) -> usize {
+        unsafe { (*current_thread()).tls[self.0] }
+    }
+
+    /// Sets the value of the slot in the current thread.
+    #[inline]
+    pub fn set(self, value: usize) {
+        unsafe { (*current_thread()).tls[self.0] = value };
+    }
+}
+
+/// Gives back every slot, for tests booting the kernel more than once.
+#[cfg(test)]
+pub(crate) fn reset() {
+    KEY_USED.with(|used| *used = 0);
+    DESTRUCTORS.with(|destructors| *destructors = [None; TLS_MAX]);
+}
+
+/// Calls the destructors of the non-zero slots of an exiting thread.
+pub(crate) fn destruct(thread: &mut Thread) {
+    for (i, value) in thread.tls.iter_mut().enumerate() {
+        let value = core::mem::take(value);
+        let destructor = DESTRUCTORS.with(|destructors| destructors[i]);
+        if let (true, Some(destructor)) = (value != 0, destructor) {
+            destructor(value);
+        }
+    }
+}
+
+/// A typed thread-local value, declared with [`thread_local!`](crate::thread_local).
+///
+/// Each thread gets its own value from the kernel heap on first access,
+/// which is dropped when the thread exits.
+#[cfg(feature = "mem-heap")]
+pub struct LocalKey<T: 'static> {
+    init: fn() -> T,
+    /// index of the slot plus one, `0` before the first access
+    key: core::sync::atomic::AtomicUsize,
+}
+
+#[cfg(feature = "mem-heap")]
+impl<T: 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self {
+            init,
+            key: core::sync::atomic::AtomicUsize::new(0),
+        }
+    }
+
+    /// Calls `f` with the value of the current thread.
+    ///
+    /// Panics if no slot is free for the first access.
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        let key = self.key();
+        let mut ptr = key.get() as *const T;
+        if ptr.is_null() {
+            ptr = alloc::boxed::Box::into_raw(alloc::boxed::Box::new((self.init)()));
+            key.set(ptr as usize);
+        }
+        f(unsafe { &*ptr })
+    }
+
+    fn key(&self) -> Key {
+        use core::sync::atomic::Ordering::Relaxed;
+
+        let _guard = InterruptFreeGuard::new();
+        match self.key.load(Relaxed) {
+            0 => {
+                let key = Key::create(Some(drop_boxed::<T>)).expect("no free thread-local slot");
+                self.key.store(key.0 + 1, Relaxed);
+                key
+            }
+            n => Key(n - 1),
+        }
+    }
+}
+
+#[cfg(feature = "mem-heap")]
+fn drop_boxed<T>(value: usize) {
+    drop(unsafe { alloc::boxed::Box::from_raw(value as *mut T) });
+}
+
+/// Declares a static [`LocalKey`](crate::tls::LocalKey), like `std::thread_local!`.
+#[cfg(feature = "mem-heap")]
+#[macro_export]
+macro_rules! thread_local {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr $(;)?) => {
+        $(#[$attr])*
+        $vis static $name: $crate::tls::LocalKey<$ty> = $crate::tls::LocalKey::new(|| $init);
+    };
+}
+
+#[test]
+fn test_key() {
+    use crate::sim::{log, Sim};
+    use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+    use std::vec::Vec;
+
+    static DESTRUCTED: AtomicUsize = AtomicUsize::new(0);
+    fn destructor(value: usize) {
+        DESTRUCTED.fetch_add(value, Relaxed);
+    }
+
+    let mut sim = Sim::new(0);
+    reset();
+    let keys = (0..TLS_MAX)
+        .map(|_| Key::create(None).unwrap())
+        .collect::<Vec<_>>();
+    assert!((1..TLS_MAX).all(|i| !keys[..i].contains(&keys[i])));
+    assert_eq!(Key::create(None), Err(Error::Full));
+    reset();
+
+    let key = Key::create(Some(destructor)).unwrap();
+    for (name, value) in [("a", 3), ("b", 4), ("c", 0)] {
+        sim.spawn(name, 1, move || {
+            if key.get() == 0 {
+                key.set(value);
+            }
+            crate::thread::yield_now();
+            if key.get() == value {
+                log(name);
+            }
+        });
+    }
+    assert_eq!([(0, "a"), (0, "b"), (0, "c")], &*sim.run(1));
+    // only non-zero values
+    assert_eq!(DESTRUCTED.load(Relaxed), 7);
+    reset();
+}
+
+#[cfg(feature = "mem-heap")]
+#[test]
+fn test_local_key() {
+    use crate::sim::{log, Sim};
+    use core::{
+        cell::Cell,
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+    };
+
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    struct Counter(Cell<usize>);
+    impl Drop for Counter {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(self.0.get(), Relaxed);
+        }
+    }
+    crate::thread_local! {
+        static COUNTER: Counter = Counter(Cell::new(0));
+    }
+
+    let mut sim = Sim::new(0);
+    reset();
+    for (name, n) in [("a", 2), ("b", 5)] {
+        sim.spawn(name, 1, move || {
+            for _ in 0..n {
+                COUNTER.with(|counter| counter.0.set(counter.0.get() + 1));
+                crate::thread::yield_now();
+            }
+            if COUNTER.with(|counter| counter.0.get()) == n {
+                log(name);
+            }
+        });
+    }
+    assert_eq!([(0, "a"), (0, "b")], &*sim.run(1));
+    // every value is dropped on exit
+    assert_eq!(DROPPED.load(Relaxed), 7);
+    reset();
+}